@@ -1,12 +1,14 @@
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
-use std::{fmt, fs, ops::RangeBounds};
+use std::{fmt, fs, ops::RangeBounds, path::Path};
+
+use crate::config;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AgendaPoint {
     pub title: String,
     pub adder: String,
-    pub timestamp: DateTime<Local>,
+    pub timestamp: DateTime<FixedOffset>,
 }
 
 impl fmt::Display for AgendaPoint {
@@ -16,11 +18,18 @@ impl fmt::Display for AgendaPoint {
             "{}: {} ({})",
             self.adder,
             self.title,
-            self.timestamp.format("%B %d -- w%V-%u")
+            self.timestamp
+                .with_timezone(&config::time_zone())
+                .format("%B %d -- w%V-%u")
         )
     }
 }
 
+/// Where the points removed by the last clear are kept.
+pub const CLEARED_FILE: &str = "cleared.json";
+/// How long a cleared agenda can be brought back.
+pub const UNDO_WINDOW: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Agenda {
     pub points: Vec<AgendaPoint>,
@@ -70,6 +79,76 @@ impl Agenda {
         agenda.write();
         Ok(())
     }
+
+    /// Removes every point and returns them. They are kept in `cleared_file`
+    /// so that [`Agenda::undo_clear`] can bring them back.
+    pub fn clear(cleared_file: impl AsRef<Path>) -> Vec<AgendaPoint> {
+        let mut agenda = Self::read();
+        let points = agenda.clear_points(cleared_file.as_ref(), Utc::now());
+        agenda.write();
+        points
+    }
+
+    /// Puts back the points from the last clear, before anything added since,
+    /// if it was less than [`UNDO_WINDOW`] ago. Returns the restored points.
+    pub fn undo_clear(cleared_file: impl AsRef<Path>) -> Result<Vec<AgendaPoint>, String> {
+        let mut agenda = Self::read();
+        let points = agenda.restore_cleared(cleared_file.as_ref(), Utc::now())?;
+        agenda.write();
+        Ok(points)
+    }
+
+    fn clear_points(&mut self, cleared_file: &Path, now: DateTime<Utc>) -> Vec<AgendaPoint> {
+        let points = std::mem::take(&mut self.points);
+        if !points.is_empty() {
+            ClearedAgenda {
+                points: points.clone(),
+                cleared_at: now,
+            }
+            .write(cleared_file);
+        }
+        points
+    }
+
+    fn restore_cleared(
+        &mut self,
+        cleared_file: &Path,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<AgendaPoint>, String> {
+        let cleared =
+            ClearedAgenda::read(cleared_file).ok_or_else(|| "Nothing to undo".to_string())?;
+        if now - cleared.cleared_at > Duration::from_std(UNDO_WINDOW).unwrap() {
+            return Err("Too late to undo".to_string());
+        }
+        self.points.splice(0..0, cleared.points.iter().cloned());
+        let _ = fs::remove_file(cleared_file);
+        Ok(cleared.points)
+    }
+}
+
+/// The points removed by the last [`Agenda::clear`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ClearedAgenda {
+    points: Vec<AgendaPoint>,
+    cleared_at: DateTime<Utc>,
+}
+
+impl ClearedAgenda {
+    fn read(file: &Path) -> Option<Self> {
+        let s = fs::read_to_string(file).ok()?;
+        Some(
+            serde_json::from_str(&s)
+                .unwrap_or_else(|e| panic!("Error parsing {}: {}", file.display(), e)),
+        )
+    }
+
+    fn write(&self, file: &Path) {
+        fs::write(
+            file,
+            serde_json::to_string_pretty(&self).expect("Can't serialize cleared agenda"),
+        )
+        .unwrap_or_else(|e| panic!("Can't write {}: {}", file.display(), e));
+    }
 }
 
 impl fmt::Display for Agenda {
@@ -90,3 +169,85 @@ impl fmt::Display for Agenda {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A cleared file of its own for every test.
+    fn cleared_file(name: &str) -> PathBuf {
+        let file = std::env::temp_dir().join(format!(
+            "kodapa-cleared-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&file);
+        file
+    }
+
+    fn agenda(titles: &[&str]) -> Agenda {
+        Agenda {
+            points: titles
+                .iter()
+                .map(|title| AgendaPoint {
+                    title: title.to_string(),
+                    adder: "kodapa".to_string(),
+                    timestamp: Utc::now().into(),
+                })
+                .collect(),
+        }
+    }
+
+    fn titles(points: &[AgendaPoint]) -> Vec<&str> {
+        points.iter().map(|point| point.title.as_str()).collect()
+    }
+
+    #[test]
+    fn undo_within_window() {
+        let file = cleared_file("undo");
+        let now = Utc::now();
+        let mut agenda = agenda(&["Budget", "Fika"]);
+        let cleared = agenda.clear_points(&file, now);
+        assert_eq!(titles(&cleared), ["Budget", "Fika"]);
+        assert!(agenda.points.is_empty());
+
+        // Points added since end up after the restored ones.
+        agenda.points = self::agenda(&["Sittning"]).points;
+        let later = now + Duration::from_std(UNDO_WINDOW).unwrap();
+        let restored = agenda.restore_cleared(&file, later).unwrap();
+        assert_eq!(titles(&restored), ["Budget", "Fika"]);
+        assert_eq!(titles(&agenda.points), ["Budget", "Fika", "Sittning"]);
+
+        // It can only be undone once.
+        assert!(!file.exists());
+        assert!(agenda.restore_cleared(&file, later).is_err());
+    }
+
+    #[test]
+    fn undo_after_window() {
+        let file = cleared_file("expired");
+        let now = Utc::now();
+        let mut agenda = agenda(&["Budget"]);
+        agenda.clear_points(&file, now);
+        let later = now + Duration::from_std(UNDO_WINDOW).unwrap() + Duration::seconds(1);
+        assert_eq!(
+            agenda.restore_cleared(&file, later).unwrap_err(),
+            "Too late to undo"
+        );
+        assert!(agenda.points.is_empty());
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn undo_without_clear() {
+        let file = cleared_file("nothing");
+        let mut agenda = agenda(&[]);
+        // Clearing an empty agenda leaves nothing to undo either.
+        assert!(agenda.clear_points(&file, Utc::now()).is_empty());
+        assert_eq!(
+            agenda.restore_cleared(&file, Utc::now()).unwrap_err(),
+            "Nothing to undo"
+        );
+    }
+}
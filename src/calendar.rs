@@ -1,119 +1,241 @@
-use chrono::{DateTime, Duration, Local};
-use hyper::{Body, Client, Request};
-use hyper_tls::HttpsConnector;
+use async_trait::async_trait;
+use chrono::Utc;
+use hyper::Body;
 use serde::de::DeserializeOwned;
-use std::convert::TryInto;
-use tokio::sync::mpsc;
-use url::Url;
-use yup_oauth2::AccessToken;
-
-use crate::calendar::model::Timestamp;
-use crate::error::{BodyParseError, RequestError};
-
-use self::model::events::{Event, EventsListRequest, EventsListResponse};
-
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    config::MeetingMatcher,
+    error::{BodyParseError, RequestError},
+    kodapa,
+};
+
+use self::{changes::ExpectedChanges, model::events::Event, scheduler::Scheduler};
+
+pub use self::{
+    auth::DiscordFlowDelegate, cache::EventCache, caldav::CalDavSource, client::CalendarClient,
+    ics::IcsSource, scheduler::Delivery,
+};
+
+mod auth;
+mod cache;
+mod caldav;
+mod changes;
+mod client;
+pub mod ics;
+pub mod meetings;
 pub mod model;
+pub mod push;
+mod scheduler;
+
+const SCOPES: [&str; 1] = ["https://www.googleapis.com/auth/calendar"];
+/// How long to wait before trying to get a token again after failing.
+const AUTH_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+/// How often the local event cache is synced with Google.
+const SYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// How often to sync anyway when the source notifies us of changes, in case a
+/// sync failed or a notification was lost.
+const NOTIFIED_SYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Sent to [`handle`] by the rest of the bot.
+#[derive(Debug)]
+pub enum Request {
+    /// The event with this id was just created, moved or deleted by the bot,
+    /// so it shouldn't be announced.
+    ExpectChange(String),
+    /// The first upcoming meeting matching the `matcher`th meeting matcher, as
+    /// of the last sync. For commands that can't ask the source themselves.
+    NextMeeting {
+        matcher: usize,
+        reply: oneshot::Sender<Option<Event>>,
+    },
+}
 
-const BASE_URL: &'static str = "https://www.googleapis.com/calendar/v3/";
-const SCOPES: [&'static str; 1] = ["https://www.googleapis.com/auth/calendar"];
+/// Somewhere calendar events come from, e.g. Google Calendar or an ICS feed.
+#[async_trait]
+pub trait CalendarSource: Send {
+    /// Brings the events up to date. Returns whether anything changed.
+    async fn sync(&mut self) -> Result<bool, RequestError>;
 
-pub async fn handle(sender: mpsc::UnboundedSender<Event>) {
-    let mut token = get_token().await.unwrap();
-    let calendar_id = std::env::var("CALENDAR_ID").expect("missing CALENDAR_ID");
+    /// Every event that hasn't ended yet, with recurring events expanded into
+    /// their instances.
+    fn events(&self) -> Box<dyn Iterator<Item = &Event> + '_>;
 
-    let mut last_fire = None;
+    fn get(&self, id: &str) -> Option<&Event>;
 
-    // The logic for when to send a reminder is a bit crude but it's fairly
-    // sturdy. Every 5 seconds, we poll all events that occur in the next 60
-    // minutes. If an event (with a start time, i.e. not a day event) is named
-    // "Styrelsemöte", and we haven't yet sent a reminder today, a reminder is
-    // sent.
+    /// Notifications that the source has changed, if it can tell us. When
+    /// there are, polling slows down.
+    fn take_notifications(&mut self) -> Option<mpsc::UnboundedReceiver<()>> {
+        None
+    }
+}
+
+pub async fn handle(
+    sender: mpsc::UnboundedSender<kodapa::Event>,
+    mut requests: mpsc::UnboundedReceiver<Request>,
+    meetings: Vec<MeetingMatcher>,
+    mut source: Box<dyn CalendarSource>,
+) {
+    let (delivery_sender, mut deliveries) = mpsc::unbounded_channel();
+    let mut scheduler = Scheduler::new(
+        meetings.clone(),
+        scheduler::SENT_REMINDERS_FILE,
+        delivery_sender,
+    );
+    let mut known_meetings = None;
+    let mut expected_changes = ExpectedChanges::default();
+    let mut notifications = source.take_notifications();
+    let mut sync_interval = tokio::time::interval(match notifications {
+        Some(_) => NOTIFIED_SYNC_INTERVAL,
+        None => SYNC_INTERVAL,
+    });
+
+    // We keep a local copy of the calendar that is synced with the source
+    // every SYNC_INTERVAL, or whenever it notifies us of a change if it can.
+    // Sources that notify us are still synced every NOTIFIED_SYNC_INTERVAL, so
+    // that a failed sync doesn't leave us with a stale copy until the next
+    // change.
+    // For Google, sync tokens are used so that only changes are transferred.
+    // Whenever the calendar changes, the scheduler is rebuilt with a queue of
+    // reminder deadlines and in between we sleep until either the next
+    // deadline or the next sync, whichever comes first.
     //
-    // This won't work ( at least not correctly) if a meeting is around midnight.
-    // (More specifically, if a meeting is planned for between 00:00 and 00:59
-    // we're going to send two reminders. The first one hour before the meeting
-    // and then another one at 00:00.)
+    // Reminders are deduplicated on the event, its start time and the reminder
+    // offset, so a meeting gets exactly one of each configured reminder
+    // regardless of when during the day it is. A reminder is only recorded as
+    // sent once Discord reports that it was posted, otherwise it is retried.
     //
-    // TODO: A better solution would be to instead poll for calendar updates
-    // using sync-tokens while concurrently waiting for the next meeting.
+    // After every sync that changed something, the next meeting of every kind
+    // is compared to what it was before so that cancelled and moved meetings
+    // can be announced. Nothing is announced after the first sync since we
+    // don't know what the meetings looked like before the bot started.
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-
-        if token.is_expired() {
-            token = get_token().await.unwrap();
+        let until_deadline = scheduler
+            .next_deadline()
+            .map(|at| (at - Utc::now()).to_std().unwrap_or_default());
+
+        tokio::select! {
+            _ = next_sync(&mut sync_interval, &mut notifications) => {
+                match source.sync().await {
+                    Ok(true) => {
+                        scheduler.rebuild(source.events());
+                        let upcoming = changes::upcoming_meetings(&meetings, source.events());
+                        if let Some(known) = &known_meetings {
+                            // Changes made while we were syncing may be in it.
+                            while let Ok(request) = requests.try_recv() {
+                                handle_request(request, &mut expected_changes, Some(known));
+                            }
+                            let changes =
+                                changes::diff(known, &upcoming, &mut expected_changes, Utc::now());
+                            for change in changes {
+                                sender.send(change).unwrap();
+                            }
+                        }
+                        known_meetings = Some(upcoming);
+                    }
+                    Ok(false) => (),
+                    Err(e) => println!("{:?}", e),
+                }
+            }
+            Some((key, delivered)) = deliveries.recv() => scheduler.report(key, delivered, Utc::now()),
+            Some(request) = requests.recv() => {
+                handle_request(request, &mut expected_changes, known_meetings.as_ref());
+            }
+            _ = sleep_for(until_deadline) => (),
         }
 
-        let now = Local::now();
-        if last_fire.map(|date| now.date() == date).unwrap_or(false) {
-            continue;
+        for (key, reminder, delivery) in scheduler.pop_due(Utc::now()) {
+            match source.get(&key.event_id) {
+                Some(meeting) => sender
+                    .send(kodapa::Event::Reminder {
+                        event: meeting.clone(),
+                        reminder: reminder.clone(),
+                        delivery,
+                    })
+                    .unwrap(),
+                // Gone since the last rebuild, so there's nothing to remind of.
+                None => delivery.report(true),
+            }
         }
-        last_fire.take();
+    }
+}
 
-        let end = now.checked_add_signed(Duration::minutes(60)).unwrap();
+fn handle_request(
+    request: Request,
+    expected_changes: &mut ExpectedChanges,
+    upcoming: Option<&changes::Meetings>,
+) {
+    match request {
+        Request::ExpectChange(id) => expected_changes.expect(id),
+        Request::NextMeeting { matcher, reply } => {
+            let now = Utc::now();
+            let next = upcoming.and_then(|upcoming| {
+                upcoming
+                    .values()
+                    .filter(|(m, _, start)| *m == matcher && *start > now)
+                    .min_by_key(|(_, _, start)| *start)
+                    .map(|(_, event, _)| event.clone())
+            });
+            // Whoever asked may have given up.
+            let _ = reply.send(next);
+        }
+    }
+}
 
-        // Try to find a styrelsemöte within 60 minutes.
-        let events = match events(&token, calendar_id.clone(), now, end).await {
-            Ok(events) => events,
-            Err(e) => {
-                println!("{:?}", e);
-                continue;
+/// Waits for the next notification or the next tick, whichever comes first.
+async fn next_sync(
+    interval: &mut tokio::time::Interval,
+    notifications: &mut Option<mpsc::UnboundedReceiver<()>>,
+) {
+    match notifications {
+        Some(receiver) => {
+            tokio::select! {
+                notification = receiver.recv() => {
+                    if notification.is_none() {
+                        // The webhook is gone, so go back to polling.
+                        *notifications = None;
+                        *interval = tokio::time::interval(SYNC_INTERVAL);
+                    }
+                }
+                _ = interval.tick() => (),
             }
-        };
-        if let Some(meeting) = events
-            .items()
-            .iter()
-            .find(|event| event.summary() == "Styrelsemöte" && event.start().date_time().is_some())
-        {
-            // Found a meeting. Notify and mark today as fired.
-            let start = match meeting.start().try_into() {
-                Ok(Timestamp::DateTime(dt)) => dt,
-                _ => panic!("malformed start of event {:?}", meeting),
-            };
-            last_fire = Some(start.date());
-            sender.send(meeting.clone()).unwrap();
+        }
+        None => {
+            interval.tick().await;
         }
     }
 }
 
-async fn events(
-    token: &AccessToken,
-    calendar_id: String,
-    start: DateTime<Local>,
-    end: DateTime<Local>,
-) -> Result<EventsListResponse, RequestError> {
-    let request = EventsListRequest::new(calendar_id)
-        .max_results(50) // Should be enough to not warrant paging
-        .order_by("startTime".to_string())
-        .single_events(true)
-        .time_min(start)
-        .time_max(end);
-
-    Ok(request.request(BASE_URL, token).await?)
+/// Sleeps for `duration`, or forever if there is nothing to wait for.
+async fn sleep_for(duration: Option<std::time::Duration>) {
+    match duration {
+        Some(duration) => tokio::time::sleep(duration).await,
+        None => futures_util::future::pending().await,
+    }
 }
 
-async fn get_token() -> Option<AccessToken> {
-    let secret = yup_oauth2::read_application_secret("client_secret.json")
-        .await
-        .ok()?;
-    let authenticator = yup_oauth2::DeviceFlowAuthenticator::builder(secret)
-        .persist_tokens_to_disk("tokens.json")
-        .build()
-        .await
-        .ok()?;
-
-    authenticator.token(&SCOPES).await.ok()
+/// Makes sure that we have a token, retrying until we do. If the device flow
+/// is used and the persisted token is invalid, this waits for someone to
+/// authorize the bot.
+async fn wait_for_token(client: &CalendarClient) {
+    loop {
+        match client.authenticator().token(&SCOPES).await {
+            Ok(_) => return,
+            Err(e) => {
+                println!("calendar: unable to get token: {}", e);
+                tokio::time::sleep(AUTH_RETRY_DELAY).await;
+            }
+        }
+    }
 }
 
-async fn request(token: &AccessToken, url: &Url, body: Body) -> Result<Body, RequestError> {
-    let request = Request::builder()
-        .uri(url.as_str())
-        .header("Authorization", format!("OAuth {}", token.as_str()))
-        .body(body)?;
-
-    let https = HttpsConnector::new();
-    let client = Client::builder().build(https);
-    let response = client.request(request).await?;
-    Ok(response.into_body())
+/// Reads a response body that is expected to be empty. The body still has to
+/// be read for the connection to be reused.
+async fn drain_body(body: Body) -> Result<(), BodyParseError> {
+    hyper::body::to_bytes(body)
+        .await
+        .map_err(BodyParseError::BodyError)?;
+    Ok(())
 }
 
 async fn parse_json_body<T: DeserializeOwned>(body: Body) -> Result<T, BodyParseError> {
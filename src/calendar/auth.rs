@@ -0,0 +1,89 @@
+//! Authentication against Google, using the flow chosen in the config.
+
+use std::{future::Future, io, pin::Pin};
+use tokio::sync::broadcast;
+use yup_oauth2::{
+    authenticator::DefaultAuthenticator,
+    authenticator_delegate::{DeviceAuthResponse, DeviceFlowDelegate},
+    DeviceFlowAuthenticator, InstalledFlowAuthenticator, InstalledFlowReturnMethod,
+    ServiceAccountAuthenticator,
+};
+
+use crate::{config::AuthConfig, kodapa};
+
+/// Sends device flow codes to Discord so that someone can authorize the bot
+/// without access to the server console.
+pub struct DiscordFlowDelegate {
+    sender: broadcast::Sender<kodapa::Event>,
+}
+
+impl DiscordFlowDelegate {
+    pub fn new(sender: broadcast::Sender<kodapa::Event>) -> Self {
+        Self { sender }
+    }
+}
+
+impl DeviceFlowDelegate for DiscordFlowDelegate {
+    fn present_user_code<'a>(
+        &'a self,
+        response: &'a DeviceAuthResponse,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            println!(
+                "calendar: authorization required, enter {} at {}",
+                response.user_code, response.verification_uri
+            );
+            let _ = self.sender.send(kodapa::Event::AuthorizationRequired {
+                url: response.verification_uri.clone(),
+                code: response.user_code.clone(),
+                expires_at: response.expires_at,
+            });
+        })
+    }
+}
+
+/// Builds the authenticator chosen in the config. `delegate` is used to
+/// present the code if the device flow is used, otherwise it is printed.
+pub async fn authenticator(
+    config: &AuthConfig,
+    delegate: Option<DiscordFlowDelegate>,
+) -> io::Result<DefaultAuthenticator> {
+    match config {
+        AuthConfig::Device {
+            client_secret,
+            token_file,
+        } => {
+            let secret = yup_oauth2::read_application_secret(client_secret).await?;
+            let builder =
+                DeviceFlowAuthenticator::builder(secret).persist_tokens_to_disk(token_file);
+            match delegate {
+                Some(delegate) => builder.flow_delegate(Box::new(delegate)).build().await,
+                None => builder.build().await,
+            }
+        }
+        AuthConfig::Installed {
+            client_secret,
+            token_file,
+            http_redirect,
+        } => {
+            let secret = yup_oauth2::read_application_secret(client_secret).await?;
+            let method = if *http_redirect {
+                InstalledFlowReturnMethod::HTTPRedirect
+            } else {
+                InstalledFlowReturnMethod::Interactive
+            };
+            InstalledFlowAuthenticator::builder(secret, method)
+                .persist_tokens_to_disk(token_file)
+                .build()
+                .await
+        }
+        AuthConfig::ServiceAccount { key_file, subject } => {
+            let key = yup_oauth2::read_service_account_key(key_file).await?;
+            let builder = ServiceAccountAuthenticator::builder(key);
+            match subject {
+                Some(subject) => builder.subject(subject).build().await,
+                None => builder.build().await,
+            }
+        }
+    }
+}
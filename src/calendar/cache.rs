@@ -0,0 +1,161 @@
+//! A local copy of the calendar that is kept up to date using incremental
+//! syncs.
+//!
+//! See `https://developers.google.com/calendar/api/guides/sync`.
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    calendar::{wait_for_token, CalendarClient, CalendarSource},
+    config,
+    error::RequestError,
+};
+
+use super::model::events::{Event, EventsListRequest};
+
+/// How long a sync waits for a token before giving up until the next sync.
+/// Getting a token is usually quick, unless someone has to authorize the bot.
+const TOKEN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The events of a Google calendar.
+pub struct EventCache {
+    client: &'static CalendarClient,
+    events: HashMap<String, Event>,
+    sync_token: Option<String>,
+    notifications: Option<mpsc::UnboundedReceiver<()>>,
+    /// Completes once we have a token. Kept between syncs while waiting for
+    /// someone to authorize the bot.
+    token: Option<oneshot::Receiver<()>>,
+}
+
+#[async_trait]
+impl CalendarSource for EventCache {
+    /// Brings the cache up to date. Does an incremental sync if we have a sync
+    /// token and falls back to a full sync if we don't or if Google tells us
+    /// that the token has expired. Returns whether anything changed.
+    ///
+    /// Nothing is synced while there's no token, since that would keep the
+    /// reminders waiting for as long as it takes to authorize the bot.
+    async fn sync(&mut self) -> Result<bool, RequestError> {
+        let client = self.client;
+        if !self.has_token().await {
+            return Ok(false);
+        }
+        if let Some(sync_token) = self.sync_token.clone() {
+            let request = EventsListRequest::new(client.calendar_id().to_string())
+                .single_events(true)
+                .time_zone(config::time_zone().name().to_string())
+                .sync_token(sync_token);
+            match self.apply(client, request, false).await {
+                Err(RequestError::Gone) => {
+                    println!("calendar: sync token expired, doing a full sync");
+                }
+                res => return res,
+            }
+        }
+        self.full_sync(client).await
+    }
+
+    fn events(&self) -> Box<dyn Iterator<Item = &Event> + '_> {
+        Box::new(self.events.values())
+    }
+
+    fn get(&self, id: &str) -> Option<&Event> {
+        self.events.get(id)
+    }
+
+    fn take_notifications(&mut self) -> Option<mpsc::UnboundedReceiver<()>> {
+        self.notifications.take()
+    }
+}
+
+impl EventCache {
+    pub fn new(client: &'static CalendarClient) -> Self {
+        Self {
+            client,
+            events: HashMap::new(),
+            sync_token: None,
+            notifications: None,
+            token: None,
+        }
+    }
+
+    /// Syncs when something is received on `notifications`, and only polls
+    /// now and then, see [`crate::calendar::push`].
+    pub fn with_notifications(mut self, notifications: mpsc::UnboundedReceiver<()>) -> Self {
+        self.notifications = Some(notifications);
+        self
+    }
+
+    /// Gets a token in the background and waits a little for it.
+    async fn has_token(&mut self) -> bool {
+        let client = self.client;
+        let token = self.token.get_or_insert_with(|| {
+            let (sender, receiver) = oneshot::channel();
+            tokio::spawn(async move {
+                wait_for_token(client).await;
+                let _ = sender.send(());
+            });
+            receiver
+        });
+        match tokio::time::timeout(TOKEN_TIMEOUT, token).await {
+            Ok(_) => {
+                self.token = None;
+                true
+            }
+            Err(_) => {
+                println!("calendar: waiting for a token, skipping sync");
+                false
+            }
+        }
+    }
+
+    async fn full_sync(&mut self, client: &CalendarClient) -> Result<bool, RequestError> {
+        self.sync_token = None;
+        // Events that ended more than a day ago are never interesting.
+        let request = EventsListRequest::new(client.calendar_id().to_string())
+            .single_events(true)
+            .time_zone(config::time_zone().name().to_string())
+            .time_min(Utc::now() - Duration::days(1));
+        self.apply(client, request, true).await?;
+        Ok(true)
+    }
+
+    /// Runs a list request, following all pages, and applies the result to
+    /// the cache. If `replace` is set, the cache is cleared first. Nothing is
+    /// changed if the request fails, so a failed sync never looks like all
+    /// events were removed.
+    async fn apply(
+        &mut self,
+        client: &CalendarClient,
+        request: EventsListRequest,
+        replace: bool,
+    ) -> Result<bool, RequestError> {
+        let response = request.request_all(client).await?;
+        if replace {
+            self.events.clear();
+        }
+        self.sync_token = response.next_sync_token().clone();
+        let items = response.into_items();
+        let changed = !items.is_empty();
+        for event in items {
+            if event.is_cancelled() {
+                self.events.remove(event.id());
+            } else {
+                self.events.insert(event.id().to_string(), event);
+            }
+        }
+        self.prune();
+        Ok(changed)
+    }
+
+    /// Forgets events that have already ended.
+    fn prune(&mut self) {
+        let now = Utc::now();
+        self.events
+            .retain(|_, event| event.end().instant().is_none_or(|end| end > now));
+    }
+}
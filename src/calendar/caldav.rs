@@ -0,0 +1,453 @@
+//! A calendar on a CalDAV server, e.g. Nextcloud or Radicale.
+//!
+//! The first sync asks for every event in the coming year with a
+//! `calendar-query` REPORT (RFC 4791). After that, a `sync-collection` REPORT
+//! (RFC 6578) tells us which calendar objects changed and only those are
+//! fetched again with `calendar-multiget`. Servers without sync support get a
+//! full query every time.
+//!
+//! The calendar data is parsed by the iCalendar backend, so recurring events
+//! are expanded the same way. To try it against a local Radicale, use e.g.
+//!
+//! ```json
+//! { "type": "caldav", "url": "http://localhost:5232/user/calendar/" }
+//! ```
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use hyper::{header, Body, Method, Request, StatusCode};
+use quick_xml::{escape::escape, events::Event as XmlEvent, Reader};
+use std::{collections::HashMap, io};
+use url::Url;
+
+use crate::{
+    calendar::{
+        client::{self, HttpClient},
+        ics,
+        model::events::Event,
+        CalendarSource,
+    },
+    config::CalendarConfig,
+    error::{BodyParseError, RequestError},
+};
+
+const DAV_NAMESPACES: &str = r#"xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav""#;
+
+pub struct CalDavSource {
+    /// The calendar collection.
+    url: Url,
+    /// Basic auth from `CALDAV_USERNAME` and `CALDAV_PASSWORD`, if set.
+    authorization: Option<String>,
+    http: HttpClient,
+    timeout: std::time::Duration,
+    user_agent: String,
+    /// The iCalendar data of every calendar object, by href.
+    objects: HashMap<String, String>,
+    sync_token: Option<String>,
+    events: HashMap<String, Event>,
+}
+
+impl CalDavSource {
+    pub fn new(url: &str, config: &CalendarConfig) -> io::Result<Self> {
+        let url = Url::parse(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let authorization = std::env::var("CALDAV_USERNAME").ok().map(|username| {
+            let password = std::env::var("CALDAV_PASSWORD").unwrap_or_default();
+            format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", username, password))
+            )
+        });
+        Ok(Self {
+            url,
+            authorization,
+            http: client::http_client(config)?,
+            timeout: std::time::Duration::from_secs(config.timeout),
+            user_agent: config.user_agent.clone(),
+            objects: HashMap::new(),
+            sync_token: None,
+            events: HashMap::new(),
+        })
+    }
+
+    /// Sends a WebDAV request to the calendar collection and parses the
+    /// multistatus response.
+    async fn request(
+        &self,
+        method: &str,
+        depth: &str,
+        body: String,
+    ) -> Result<Multistatus, RequestError> {
+        let method = Method::from_bytes(method.as_bytes()).expect("invalid method");
+        let mut request = Request::builder()
+            .method(method)
+            .uri(self.url.as_str())
+            .header("Depth", depth)
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .header(header::USER_AGENT, &self.user_agent);
+        if let Some(authorization) = &self.authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let request = request.body(Body::from(body))?;
+
+        let response = tokio::time::timeout(self.timeout, self.http.request(request))
+            .await
+            .map_err(|_| RequestError::Timeout)??;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(RequestError::ApiError {
+                status,
+                error: None,
+            });
+        }
+        let bytes = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| RequestError::ResponseError(BodyParseError::BodyError(e)))?;
+        Multistatus::parse(&String::from_utf8_lossy(&bytes))
+            .map_err(|e| RequestError::ResponseError(BodyParseError::XmlError(e)))
+    }
+
+    /// Fetches every event in the coming year and starts over with a new sync
+    /// token, if the server supports it.
+    async fn full_sync(&mut self) -> Result<(), RequestError> {
+        // The token is fetched first so that changes made during the query
+        // are picked up by the next sync.
+        let sync_token = self
+            .request(
+                "PROPFIND",
+                "0",
+                format!(
+                    r#"<?xml version="1.0" encoding="utf-8"?><d:propfind {}><d:prop><d:sync-token/></d:prop></d:propfind>"#,
+                    DAV_NAMESPACES
+                ),
+            )
+            .await?
+            .sync_token;
+
+        let now = Utc::now();
+        let start = now - Duration::days(1);
+        let end = now + Duration::days(ics::LOOK_AHEAD_DAYS);
+        let multistatus = self
+            .request(
+                "REPORT",
+                "1",
+                format!(
+                    r#"<?xml version="1.0" encoding="utf-8"?><c:calendar-query {}><d:prop><d:getetag/><c:calendar-data/></d:prop><c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VEVENT"><c:time-range start="{}" end="{}"/></c:comp-filter></c:comp-filter></c:filter></c:calendar-query>"#,
+                    DAV_NAMESPACES,
+                    start.format("%Y%m%dT%H%M%SZ"),
+                    end.format("%Y%m%dT%H%M%SZ"),
+                ),
+            )
+            .await?;
+
+        self.objects = multistatus
+            .responses
+            .into_iter()
+            .filter_map(|response| Some((response.href, response.calendar_data?)))
+            .collect();
+        self.sync_token = sync_token;
+        Ok(())
+    }
+
+    /// Fetches the calendar objects that changed since `sync_token`.
+    async fn sync_changes(&mut self, sync_token: &str) -> Result<(), RequestError> {
+        let multistatus = self
+            .request(
+                "REPORT",
+                "0",
+                format!(
+                    r#"<?xml version="1.0" encoding="utf-8"?><d:sync-collection {}><d:sync-token>{}</d:sync-token><d:sync-level>1</d:sync-level><d:prop><d:getetag/></d:prop></d:sync-collection>"#,
+                    DAV_NAMESPACES,
+                    xml_escape(sync_token),
+                ),
+            )
+            .await?;
+
+        let changed = remove_deleted(&mut self.objects, multistatus.responses);
+        if !changed.is_empty() {
+            let hrefs: String = changed
+                .iter()
+                .map(|href| format!("<d:href>{}</d:href>", xml_escape(href)))
+                .collect();
+            let objects = self
+                .request(
+                    "REPORT",
+                    "1",
+                    format!(
+                        r#"<?xml version="1.0" encoding="utf-8"?><c:calendar-multiget {}><d:prop><d:getetag/><c:calendar-data/></d:prop>{}</c:calendar-multiget>"#,
+                        DAV_NAMESPACES, hrefs,
+                    ),
+                )
+                .await?;
+            for response in objects.responses {
+                if let Some(calendar_data) = response.calendar_data {
+                    self.objects.insert(response.href, calendar_data);
+                }
+            }
+        }
+
+        self.sync_token = multistatus.sync_token;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CalendarSource for CalDavSource {
+    async fn sync(&mut self) -> Result<bool, RequestError> {
+        match self.sync_token.clone() {
+            Some(sync_token) => match self.sync_changes(&sync_token).await {
+                // RFC 6578 says 403, but not every server agrees.
+                Err(RequestError::ApiError { status, .. })
+                    if status == StatusCode::FORBIDDEN
+                        || status == StatusCode::CONFLICT
+                        || status == StatusCode::PRECONDITION_FAILED =>
+                {
+                    println!("caldav: sync token rejected, doing a full sync");
+                    self.full_sync().await?
+                }
+                res => res?,
+            },
+            None => self.full_sync().await?,
+        }
+
+        let mut events = HashMap::new();
+        for (href, calendar_data) in &self.objects {
+            match ics::upcoming_events(calendar_data) {
+                Ok(object_events) => events.extend(object_events),
+                Err(e) => println!("caldav: unable to parse {}: {}", href, e),
+            }
+        }
+        Ok(ics::replace_events(&mut self.events, events))
+    }
+
+    fn events(&self) -> Box<dyn Iterator<Item = &Event> + '_> {
+        Box::new(self.events.values())
+    }
+
+    fn get(&self, id: &str) -> Option<&Event> {
+        self.events.get(id)
+    }
+}
+
+/// Removes the objects that a `sync-collection` response says are gone and
+/// returns the hrefs of the ones that changed.
+fn remove_deleted(objects: &mut HashMap<String, String>, responses: Vec<Response>) -> Vec<String> {
+    let mut changed = Vec::new();
+    for response in responses {
+        if response.status == Some(StatusCode::NOT_FOUND.as_u16()) {
+            objects.remove(&response.href);
+        } else {
+            changed.push(response.href);
+        }
+    }
+    changed
+}
+
+fn xml_escape(s: &str) -> String {
+    String::from_utf8_lossy(&escape(s.as_bytes())).into_owned()
+}
+
+/// The parts of a WebDAV multistatus response that we use.
+#[derive(Debug, Default)]
+struct Multistatus {
+    responses: Vec<Response>,
+    sync_token: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Response {
+    href: String,
+    /// The status of the whole response, which is only set for removed objects
+    /// in a `sync-collection` response.
+    status: Option<u16>,
+    calendar_data: Option<String>,
+}
+
+impl Multistatus {
+    /// Reads the response, ignoring namespace prefixes since servers pick
+    /// their own.
+    fn parse(xml: &str) -> Result<Self, quick_xml::Error> {
+        let mut reader = Reader::from_str(xml);
+        reader.expand_empty_elements(true);
+
+        let mut multistatus = Self::default();
+        let mut response = Response::default();
+        let mut propstat_status = None;
+        let mut calendar_data = None;
+        let mut path: Vec<Vec<u8>> = Vec::new();
+        let mut text = String::new();
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event(&mut buf)? {
+                XmlEvent::Start(e) => {
+                    path.push(e.local_name().to_vec());
+                    text.clear();
+                }
+                XmlEvent::Text(e) => text.push_str(&e.unescape_and_decode(&reader)?),
+                XmlEvent::CData(e) => text.push_str(&String::from_utf8_lossy(&e.into_inner())),
+                XmlEvent::End(_) => {
+                    let name = path.pop().unwrap_or_default();
+                    match (name.as_slice(), path.last().map(Vec::as_slice)) {
+                        (b"href", Some(b"response")) => response.href = text.trim().to_string(),
+                        (b"status", Some(b"response")) => response.status = parse_status(&text),
+                        (b"status", Some(b"propstat")) => propstat_status = parse_status(&text),
+                        (b"calendar-data", _) => calendar_data = Some(text.trim().to_string()),
+                        // Servers without sync support send it empty.
+                        (b"sync-token", _) if !text.trim().is_empty() => {
+                            multistatus.sync_token = Some(text.trim().to_string())
+                        }
+                        // Properties that the server couldn't give us are in
+                        // their own propstat with an error status.
+                        (b"propstat", _) => {
+                            if propstat_status.is_some_and(|status| (200..300).contains(&status)) {
+                                response.calendar_data =
+                                    calendar_data.take().or(response.calendar_data.take());
+                            }
+                            propstat_status = None;
+                            calendar_data = None;
+                        }
+                        (b"response", _) => {
+                            multistatus.responses.push(std::mem::take(&mut response))
+                        }
+                        _ => (),
+                    }
+                    text.clear();
+                }
+                XmlEvent::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+        Ok(multistatus)
+    }
+}
+
+/// E.g. `HTTP/1.1 404 Not Found`.
+fn parse_status(status: &str) -> Option<u16> {
+    status.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENT: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:styrelsemote\r\nDTSTART:20300314T161500Z\r\nDTEND:20300314T171500Z\r\nSUMMARY:Styrelsemöte\r\nEND:VEVENT\r\nEND:VCALENDAR";
+
+    #[test]
+    fn calendar_query() {
+        // As sent by Radicale.
+        let xml = format!(
+            r#"<?xml version='1.0' encoding='utf-8'?>
+<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/user/calendar/styrelsemote.ics</href>
+    <propstat>
+      <prop>
+        <getetag>"1234"</getetag>
+        <C:calendar-data>{}</C:calendar-data>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+  <response>
+    <href>/user/calendar/empty.ics</href>
+    <propstat>
+      <prop>
+        <getetag>"5678"</getetag>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+</multistatus>"#,
+            EVENT
+        );
+        let multistatus = Multistatus::parse(&xml).unwrap();
+        assert_eq!(multistatus.sync_token, None);
+        assert_eq!(multistatus.responses.len(), 2);
+        let response = &multistatus.responses[0];
+        assert_eq!(response.href, "/user/calendar/styrelsemote.ics");
+        assert_eq!(response.status, None);
+        assert_eq!(response.calendar_data.as_deref(), Some(EVENT));
+        assert_eq!(multistatus.responses[1].calendar_data, None);
+
+        let events = ics::upcoming_events(response.calendar_data.as_ref().unwrap()).unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn sync_collection() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/user/calendar/moved.ics</d:href>
+    <d:propstat>
+      <d:prop><d:getetag>"2"</d:getetag></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/user/calendar/cancelled.ics</d:href>
+    <d:status>HTTP/1.1 404 Not Found</d:status>
+  </d:response>
+  <d:sync-token>http://radicale.org/ns/sync/2</d:sync-token>
+</d:multistatus>"#;
+        let multistatus = Multistatus::parse(xml).unwrap();
+        assert_eq!(
+            multistatus.sync_token.as_deref(),
+            Some("http://radicale.org/ns/sync/2")
+        );
+        assert_eq!(multistatus.responses[1].status, Some(404));
+
+        let mut objects: HashMap<_, _> = ["moved", "cancelled", "unchanged"]
+            .iter()
+            .map(|name| (format!("/user/calendar/{}.ics", name), EVENT.to_string()))
+            .collect();
+        let changed = remove_deleted(&mut objects, multistatus.responses);
+        assert_eq!(changed, vec!["/user/calendar/moved.ics".to_string()]);
+        let mut hrefs: Vec<_> = objects.keys().map(String::as_str).collect();
+        hrefs.sort_unstable();
+        assert_eq!(
+            hrefs,
+            vec!["/user/calendar/moved.ics", "/user/calendar/unchanged.ics"]
+        );
+    }
+
+    #[test]
+    fn failed_propstat() {
+        // The calendar data is in its own propstat since it couldn't be read.
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/user/calendar/secret.ics</d:href>
+    <d:propstat>
+      <d:prop><d:getetag>"3"</d:getetag></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop><cal:calendar-data>BEGIN:VCALENDAR</cal:calendar-data></d:prop>
+      <d:status>HTTP/1.1 403 Forbidden</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let multistatus = Multistatus::parse(xml).unwrap();
+        assert_eq!(multistatus.responses.len(), 1);
+        assert_eq!(multistatus.responses[0].href, "/user/calendar/secret.ics");
+        assert_eq!(multistatus.responses[0].calendar_data, None);
+    }
+
+    #[test]
+    fn empty_sync_token() {
+        // Servers without sync support, so the next sync is a full one.
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/user/calendar/</d:href>
+    <d:propstat>
+      <d:prop><d:sync-token/></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let multistatus = Multistatus::parse(xml).unwrap();
+        assert_eq!(multistatus.sync_token, None);
+    }
+}
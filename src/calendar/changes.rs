@@ -0,0 +1,269 @@
+//! Finds out what has happened to meetings between two syncs.
+//!
+//! Only the next meeting of every kind is looked at, so that moving or
+//! deleting a whole series is announced once and not for every instance.
+//! Changes the bot made itself with `/meeting` are not announced, since
+//! whoever ran the command has already been told.
+
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{calendar::model::events::Event, config::MeetingMatcher, kodapa};
+
+/// How long to wait for a change made by the bot to show up in a sync.
+const EXPECTED_CHANGE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Ids of events the bot has changed, and when.
+#[derive(Debug, Default)]
+pub struct ExpectedChanges(Vec<(String, Instant)>);
+
+impl ExpectedChanges {
+    /// Tells [`diff`] that the event with `id` was just created, moved or
+    /// deleted by the bot, so that it isn't announced.
+    pub fn expect(&mut self, id: String) {
+        self.0.push((id, Instant::now()));
+    }
+}
+
+/// The upcoming meetings we know of, keyed on event id, with the index of the
+/// matcher they match.
+pub type Meetings = HashMap<String, (usize, Event, DateTime<Utc>)>;
+
+pub fn upcoming_meetings<'a>(
+    matchers: &[MeetingMatcher],
+    events: impl IntoIterator<Item = &'a Event>,
+) -> Meetings {
+    let now = Utc::now();
+    events
+        .into_iter()
+        .filter_map(|event| {
+            let (m, start) = matchers
+                .iter()
+                .enumerate()
+                .find_map(|(m, matcher)| Some((m, matcher.start_of(event)?)))?;
+            (start > now).then(|| (event.id().to_string(), (m, event.clone(), start)))
+        })
+        .collect()
+}
+
+/// Compares the next meeting of every kind before and after a sync at `now`.
+///
+/// If the next meeting disappeared and was replaced by one that wasn't there
+/// before, it's reported as moved, which is what moving a series looks like.
+/// Otherwise a meeting that disappeared is reported as cancelled, whether it
+/// was deleted or just stopped matching.
+pub fn diff(
+    old: &Meetings,
+    new: &Meetings,
+    expected: &mut ExpectedChanges,
+    now: DateTime<Utc>,
+) -> Vec<kodapa::Event> {
+    let expected = &mut expected.0;
+    expected.retain(|(_, at)| at.elapsed() < EXPECTED_CHANGE_TTL);
+
+    let old_next = next_meetings(old);
+    let new_next = next_meetings(new);
+    let mut changes = Vec::new();
+    for (m, (id, old_event, old_start)) in old_next {
+        // Meetings that have started are not cancelled, just over.
+        if *old_start <= now {
+            continue;
+        }
+        let next = new_next.get(&m);
+        let (change, ids) = match new.get(id) {
+            Some((_, event, start)) if start != old_start => (
+                Some(kodapa::Event::MeetingRescheduled {
+                    event: event.clone(),
+                    old_start: *old_start,
+                }),
+                vec![id],
+            ),
+            Some((_, event, _)) if event.location() != old_event.location() => (
+                Some(kodapa::Event::MeetingLocationChanged {
+                    event: event.clone(),
+                    old_location: old_event.location().clone(),
+                }),
+                vec![id],
+            ),
+            Some(_) => (None, vec![]),
+            None => match next {
+                Some(&(next_id, event, _)) if !old.contains_key(next_id) => (
+                    Some(kodapa::Event::MeetingRescheduled {
+                        event: event.clone(),
+                        old_start: *old_start,
+                    }),
+                    vec![id, next_id],
+                ),
+                _ => (
+                    Some(kodapa::Event::MeetingCancelled {
+                        event: old_event.clone(),
+                    }),
+                    vec![id],
+                ),
+            },
+        };
+        let change = match change {
+            Some(change) => change,
+            None => continue,
+        };
+        let len = expected.len();
+        expected.retain(|(expected, _)| !ids.contains(&expected));
+        if expected.len() == len {
+            changes.push(change);
+        }
+    }
+    changes
+}
+
+/// The first meeting of every matcher.
+fn next_meetings(meetings: &Meetings) -> HashMap<usize, (&String, &Event, &DateTime<Utc>)> {
+    let mut next: HashMap<usize, (&String, &Event, &DateTime<Utc>)> = HashMap::new();
+    for (id, (m, event, start)) in meetings {
+        match next.get(m) {
+            Some((_, _, first)) if *first <= start => (),
+            _ => {
+                next.insert(*m, (id, event, start));
+            }
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calendar::model::GCalTimestamp, test_util::utc};
+
+    /// Meetings of the first kind with the given ids, starts and locations.
+    fn meetings(meetings: &[(&str, &str, Option<&str>)]) -> Meetings {
+        meetings
+            .iter()
+            .map(|&(id, start, location)| {
+                let start = utc(start).with_timezone(&chrono_tz::UTC);
+                let event = Event::new(
+                    id.to_string(),
+                    "Styrelsemöte".to_string(),
+                    GCalTimestamp::from_date_time(start),
+                    GCalTimestamp::from_date_time(start + chrono::Duration::hours(1)),
+                )
+                .with_location(location.map(str::to_string));
+                (id.to_string(), (0, event, start.with_timezone(&Utc)))
+            })
+            .collect()
+    }
+
+    const NOW: &str = "2030-03-01T12:00:00Z";
+
+    #[test]
+    fn rescheduled() {
+        let old = meetings(&[
+            ("a", "2030-03-14T16:15:00Z", None),
+            ("b", "2030-03-28T16:15:00Z", None),
+        ]);
+        let new = meetings(&[
+            ("a", "2030-03-15T16:15:00Z", None),
+            ("b", "2030-03-28T16:15:00Z", None),
+        ]);
+        let changes = diff(&old, &new, &mut ExpectedChanges::default(), utc(NOW));
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            kodapa::Event::MeetingRescheduled { event, old_start } => {
+                assert_eq!(event.id(), "a");
+                assert_eq!(*old_start, utc("2030-03-14T16:15:00Z"));
+            }
+            change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn relocated() {
+        let old = meetings(&[("a", "2030-03-14T16:15:00Z", Some("Café Java"))]);
+        let new = meetings(&[("a", "2030-03-14T16:15:00Z", Some("Styrelserummet"))]);
+        let changes = diff(&old, &new, &mut ExpectedChanges::default(), utc(NOW));
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            kodapa::Event::MeetingLocationChanged {
+                event,
+                old_location,
+            } => {
+                assert_eq!(event.location().as_deref(), Some("Styrelserummet"));
+                assert_eq!(old_location.as_deref(), Some("Café Java"));
+            }
+            change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn cancelled() {
+        let old = meetings(&[
+            ("a", "2030-03-14T16:15:00Z", None),
+            ("b", "2030-03-28T16:15:00Z", None),
+        ]);
+        let new = meetings(&[("b", "2030-03-28T16:15:00Z", None)]);
+        let changes = diff(&old, &new, &mut ExpectedChanges::default(), utc(NOW));
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            kodapa::Event::MeetingCancelled { event } => assert_eq!(event.id(), "a"),
+            change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn series_moved() {
+        // Moving every following instance of a series gives them new ids.
+        let old = meetings(&[
+            ("a_20300314", "2030-03-14T16:15:00Z", None),
+            ("a_20300328", "2030-03-28T16:15:00Z", None),
+        ]);
+        let new = meetings(&[
+            ("b_20300315", "2030-03-15T16:15:00Z", None),
+            ("b_20300329", "2030-03-29T16:15:00Z", None),
+        ]);
+        let changes = diff(&old, &new, &mut ExpectedChanges::default(), utc(NOW));
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            kodapa::Event::MeetingRescheduled { event, old_start } => {
+                assert_eq!(event.id(), "b_20300315");
+                assert_eq!(*old_start, utc("2030-03-14T16:15:00Z"));
+            }
+            change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn unchanged() {
+        let old = meetings(&[("a", "2030-03-14T16:15:00Z", None)]);
+        let new = meetings(&[
+            ("a", "2030-03-14T16:15:00Z", None),
+            ("b", "2030-03-28T16:15:00Z", None),
+        ]);
+        assert!(diff(&old, &new, &mut ExpectedChanges::default(), utc(NOW)).is_empty());
+    }
+
+    #[test]
+    fn expected_changes_are_skipped_once() {
+        let old = meetings(&[("a", "2030-03-14T16:15:00Z", None)]);
+        let moved = meetings(&[("a", "2030-03-15T16:15:00Z", None)]);
+        let mut expected = ExpectedChanges::default();
+        expected.expect("a".to_string());
+        assert!(diff(&old, &moved, &mut expected, utc(NOW)).is_empty());
+        // Someone else moving it back is announced.
+        assert_eq!(diff(&moved, &old, &mut expected, utc(NOW)).len(), 1);
+
+        // Either id of a moved series is enough.
+        let series = meetings(&[("b", "2030-03-15T16:15:00Z", None)]);
+        expected.expect("b".to_string());
+        assert!(diff(&old, &series, &mut expected, utc(NOW)).is_empty());
+    }
+
+    #[test]
+    fn started_meetings_are_not_cancelled() {
+        let old = meetings(&[("a", "2030-03-14T16:15:00Z", None)]);
+        let new = meetings(&[]);
+        let during = utc("2030-03-14T16:30:00Z");
+        assert!(diff(&old, &new, &mut ExpectedChanges::default(), during).is_empty());
+    }
+}
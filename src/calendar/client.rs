@@ -0,0 +1,134 @@
+//! The HTTP client all calendar requests go through.
+
+use hyper::{client::HttpConnector, Body, Client, Method, Request, StatusCode};
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_tls::HttpsConnector;
+use std::{io, time::Duration};
+use url::Url;
+use yup_oauth2::authenticator::DefaultAuthenticator;
+
+use crate::{
+    calendar::{
+        auth::{self, DiscordFlowDelegate},
+        model::ApiErrorResponse,
+        parse_json_body, SCOPES,
+    },
+    config::{CalendarConfig, Config},
+    error::RequestError,
+};
+
+pub type HttpClient = Client<ProxyConnector<HttpsConnector<HttpConnector>>>;
+
+/// How many times to retry a request that failed because of rate limits or
+/// server errors, see [`RequestError::is_retryable`]. The delay doubles every
+/// time, starting at one second.
+const MAX_RETRIES: u32 = 5;
+
+/// A long-lived client for the Google Calendar API. Keeps its connections
+/// open between requests so that we don't pay for a TLS handshake every time.
+pub struct CalendarClient {
+    http: HttpClient,
+    authenticator: DefaultAuthenticator,
+    base_url: String,
+    calendar_id: String,
+    timeout: Duration,
+    user_agent: String,
+}
+
+impl CalendarClient {
+    pub async fn new(config: &Config, delegate: Option<DiscordFlowDelegate>) -> io::Result<Self> {
+        Ok(Self {
+            http: http_client(&config.calendar)?,
+            authenticator: auth::authenticator(&config.auth, delegate).await?,
+            base_url: config.calendar.base_url.clone(),
+            calendar_id: std::env::var("CALENDAR_ID").expect("missing CALENDAR_ID"),
+            timeout: Duration::from_secs(config.calendar.timeout),
+            user_agent: config.calendar.user_agent.clone(),
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn calendar_id(&self) -> &str {
+        &self.calendar_id
+    }
+
+    pub fn authenticator(&self) -> &DefaultAuthenticator {
+        &self.authenticator
+    }
+
+    /// Sends a request and returns the body of the response if it was
+    /// successful.
+    ///
+    /// If the token is rejected, a new one is fetched and the request is
+    /// retried once. Rate limits, and server errors for requests that can
+    /// safely be sent twice, are retried with exponential backoff.
+    pub async fn request(
+        &self,
+        method: Method,
+        url: &Url,
+        body: Vec<u8>,
+    ) -> Result<Body, RequestError> {
+        let mut refreshed = false;
+        let mut retries = 0;
+        loop {
+            let token = if refreshed {
+                self.authenticator.force_refreshed_token(&SCOPES).await
+            } else {
+                self.authenticator.token(&SCOPES).await
+            }
+            .map_err(RequestError::TokenError)?;
+
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(url.as_str())
+                .header("Authorization", format!("OAuth {}", token.as_str()))
+                .header("Content-Type", "application/json")
+                .header("User-Agent", &self.user_agent)
+                .body(Body::from(body.clone()))?;
+
+            let response = tokio::time::timeout(self.timeout, self.http.request(request))
+                .await
+                .map_err(|_| RequestError::Timeout)??;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response.into_body());
+            }
+            if status == StatusCode::GONE {
+                return Err(RequestError::Gone);
+            }
+
+            let error = parse_json_body::<ApiErrorResponse>(response.into_body())
+                .await
+                .ok()
+                .map(|response| response.error);
+            let error = RequestError::ApiError { status, error };
+            if status == StatusCode::UNAUTHORIZED && !refreshed {
+                refreshed = true;
+                continue;
+            }
+            if error.is_retryable(&method) && retries < MAX_RETRIES {
+                let delay = Duration::from_secs(1 << retries);
+                println!("calendar: {}, retrying in {:?}", error, delay);
+                tokio::time::sleep(delay).await;
+                retries += 1;
+                continue;
+            }
+            return Err(error);
+        }
+    }
+}
+
+/// An HTTP client that goes through the configured proxy, if any.
+pub fn http_client(config: &CalendarConfig) -> io::Result<HttpClient> {
+    let mut connector = ProxyConnector::new(HttpsConnector::new())?;
+    if let Some(proxy) = &config.proxy {
+        let uri = proxy
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        connector.add_proxy(Proxy::new(Intercept::All, uri));
+    }
+    Ok(Client::builder().build(connector))
+}
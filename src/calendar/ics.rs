@@ -0,0 +1,827 @@
+//! A calendar read from an iCalendar (RFC 5545) file or URL, e.g. a feed
+//! exported from another calendar program.
+//!
+//! Recurring events are expanded into one event per instance, like Google does
+//! with `singleEvents`, so that the rest of the bot doesn't need to know where
+//! the events came from. Time zones are looked up by their `TZID` in the IANA
+//! database, so `VTIMEZONE` definitions are ignored and names that aren't IANA
+//! names, like Outlook's, are treated as floating time. Floating times are in
+//! the configured time zone.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use hyper::{header, Body, Request};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    calendar::{
+        client::{self, HttpClient},
+        model::{self, events::Event, GCalTimestamp},
+        CalendarSource,
+    },
+    config::{self, CalendarConfig},
+    error::{BodyParseError, RequestError},
+};
+
+use self::rrule::RRule;
+
+mod rrule;
+
+/// How far ahead recurring events are expanded.
+pub(super) const LOOK_AHEAD_DAYS: i64 = 365;
+/// How many redirects to follow when fetching a feed.
+const MAX_REDIRECTS: usize = 5;
+
+pub struct IcsSource {
+    /// A file path or an `http(s)://` or `webcal://` URL.
+    location: String,
+    http: HttpClient,
+    timeout: std::time::Duration,
+    user_agent: String,
+    events: HashMap<String, Event>,
+}
+
+impl IcsSource {
+    pub fn new(location: String, config: &CalendarConfig) -> std::io::Result<Self> {
+        Ok(Self {
+            location,
+            http: client::http_client(config)?,
+            timeout: std::time::Duration::from_secs(config.timeout),
+            user_agent: config.user_agent.clone(),
+            events: HashMap::new(),
+        })
+    }
+
+    async fn fetch(&self) -> Result<String, RequestError> {
+        let mut url = match self.location.strip_prefix("webcal://") {
+            Some(rest) => format!("https://{}", rest),
+            None if self.location.starts_with("http://")
+                || self.location.starts_with("https://") =>
+            {
+                self.location.clone()
+            }
+            None => {
+                return tokio::fs::read_to_string(&self.location)
+                    .await
+                    .map_err(RequestError::IoError)
+            }
+        };
+
+        for _ in 0..=MAX_REDIRECTS {
+            let request = Request::get(url.as_str())
+                .header(header::USER_AGENT, &self.user_agent)
+                .body(Body::empty())?;
+            let response = tokio::time::timeout(self.timeout, self.http.request(request))
+                .await
+                .map_err(|_| RequestError::Timeout)??;
+            let status = response.status();
+            if status.is_redirection() {
+                if let Some(location) = response
+                    .headers()
+                    .get(header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                {
+                    url = url::Url::parse(&url)?.join(location)?.to_string();
+                    continue;
+                }
+            }
+            if !status.is_success() {
+                return Err(RequestError::ApiError {
+                    status,
+                    error: None,
+                });
+            }
+            let bytes = hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| RequestError::ResponseError(BodyParseError::BodyError(e)))?;
+            return Ok(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        Err(RequestError::TooManyRedirects)
+    }
+}
+
+#[async_trait]
+impl CalendarSource for IcsSource {
+    async fn sync(&mut self) -> Result<bool, RequestError> {
+        let text = self.fetch().await?;
+        let events = upcoming_events(&text)
+            .map_err(|e| RequestError::ResponseError(BodyParseError::IcsError(e)))?;
+        Ok(replace_events(&mut self.events, events))
+    }
+
+    fn events(&self) -> Box<dyn Iterator<Item = &Event> + '_> {
+        Box::new(self.events.values())
+    }
+
+    fn get(&self, id: &str) -> Option<&Event> {
+        self.events.get(id)
+    }
+}
+
+/// Every event in an iCalendar file that hasn't ended yet, by id.
+pub(super) fn upcoming_events(text: &str) -> Result<HashMap<String, Event>, ParseError> {
+    let now = Utc::now();
+    Ok(parse(text)?
+        .into_iter()
+        .filter(|event| ends_after(event, now))
+        .map(|event| (event.id().to_string(), event))
+        .collect())
+}
+
+/// Replaces `old` with `new` and returns whether anything changed. Since the
+/// events are rebuilt from scratch every time, they are compared as JSON.
+pub(super) fn replace_events(
+    old: &mut HashMap<String, Event>,
+    new: HashMap<String, Event>,
+) -> bool {
+    let changed = serde_json::to_value(&*old).ok() != serde_json::to_value(&new).ok();
+    *old = new;
+    changed
+}
+
+fn ends_after(event: &Event, now: DateTime<Utc>) -> bool {
+    event.end().instant().is_none_or(|end| end > now)
+}
+
+/// A line in an iCalendar file could not be understood.
+#[derive(Debug)]
+pub struct ParseError {
+    line: String,
+    message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in \"{}\"", self.message, self.line)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A content line, e.g. `DTSTART;TZID=Europe/Stockholm:20210314T171500`.
+#[derive(Debug)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Result<Self, ParseError> {
+        let error = |message: &str| ParseError {
+            line: line.to_string(),
+            message: message.to_string(),
+        };
+        // The value is everything after the first colon that isn't part of a
+        // quoted parameter value.
+        let (head, value) = match find_unquoted(line, ':') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => return Err(error("missing value")),
+        };
+        let mut parts = split_unquoted(head, ';');
+        let name = parts.remove(0).to_ascii_uppercase();
+        let params = parts
+            .into_iter()
+            .map(|param| {
+                let (name, value) = param
+                    .split_once('=')
+                    .ok_or_else(|| error("invalid parameter"))?;
+                Ok((
+                    name.to_ascii_uppercase(),
+                    value.trim_matches('"').to_string(),
+                ))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of a `TEXT` property, with escapes removed.
+    fn text(&self) -> String {
+        let mut text = String::with_capacity(self.value.len());
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n') | Some('N') => text.push('\n'),
+                    Some(c) => text.push(c),
+                    None => (),
+                },
+                c => text.push(c),
+            }
+        }
+        text
+    }
+}
+
+/// The index of the first `c` in `s` that isn't inside double quotes.
+fn find_unquoted(s: &str, c: char) -> Option<usize> {
+    let mut quoted = false;
+    s.char_indices().find_map(|(i, ch)| {
+        if ch == '"' {
+            quoted = !quoted;
+        }
+        (!quoted && ch == c).then_some(i)
+    })
+}
+
+/// Splits `s` on `c` outside of double quotes.
+fn split_unquoted(s: &str, c: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some(i) = find_unquoted(rest, c) {
+        parts.push(&rest[..i]);
+        rest = &rest[i + 1..];
+    }
+    parts.push(rest);
+    parts
+}
+
+/// Joins lines that were folded by starting the next line with whitespace.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => (),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Where a time is.
+#[derive(Debug, Clone, Copy)]
+enum Zone {
+    Utc,
+    Named(Tz),
+    /// In the configured time zone.
+    Floating,
+}
+
+impl Zone {
+    /// Looks up a `TZID`. Some programs prefix the IANA name with a path, e.g.
+    /// `/mozilla.org/20050126_1/Europe/Stockholm`.
+    fn from_tzid(tzid: &str) -> Self {
+        let mut name = tzid;
+        loop {
+            if let Ok(tz) = name.parse() {
+                return Self::Named(tz);
+            }
+            match name.split_once('/') {
+                Some((_, rest)) => name = rest,
+                None => {
+                    println!("ics: unknown time zone {}, using the configured one", tzid);
+                    return Self::Floating;
+                }
+            }
+        }
+    }
+
+    fn tz(&self) -> Tz {
+        match self {
+            Self::Utc => Tz::UTC,
+            Self::Named(tz) => *tz,
+            Self::Floating => config::time_zone(),
+        }
+    }
+
+    /// The instant a wall clock time in this zone refers to.
+    fn resolve(&self, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+        model::resolve_local(self.tz(), time)
+    }
+
+    /// The wall clock time in this zone at `time`.
+    fn local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        time.with_timezone(&self.tz()).naive_local()
+    }
+}
+
+/// A `DATE` or `DATE-TIME` value.
+#[derive(Debug, Clone, Copy)]
+struct Time {
+    /// The wall clock time, midnight for dates.
+    local: NaiveDateTime,
+    zone: Zone,
+    all_day: bool,
+}
+
+impl Time {
+    fn parse(value: &str, tzid: Option<&str>) -> Option<Self> {
+        if value.len() == 8 {
+            let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+            return Some(Self {
+                local: date.and_hms(0, 0, 0),
+                zone: Zone::Floating,
+                all_day: true,
+            });
+        }
+        let (value, zone) = match value.strip_suffix('Z') {
+            Some(value) => (value, Zone::Utc),
+            None => (value, tzid.map(Zone::from_tzid).unwrap_or(Zone::Floating)),
+        };
+        Some(Self {
+            local: NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?,
+            zone,
+            all_day: false,
+        })
+    }
+
+    fn from_property(property: &Property) -> Option<Self> {
+        Self::parse(&property.value, property.param("TZID"))
+    }
+
+    /// The `TZID` to use for other times that belong with this one.
+    fn tzid(&self) -> Option<&'static str> {
+        match self.zone {
+            Zone::Named(tz) => Some(tz.name()),
+            _ => None,
+        }
+    }
+
+    /// The same zone as `self`, but at `local`.
+    fn at(self, local: NaiveDateTime) -> Self {
+        Self { local, ..self }
+    }
+
+    /// Identifies an instance of a recurring event. Dates and times are never
+    /// compared with each other, so dates can be used as is.
+    fn key(&self) -> Option<NaiveDateTime> {
+        if self.all_day {
+            Some(self.local)
+        } else {
+            Some(self.zone.resolve(self.local)?.naive_utc())
+        }
+    }
+
+    fn to_timestamp(self) -> Option<GCalTimestamp> {
+        if self.all_day {
+            Some(GCalTimestamp::from_date(self.local.date()))
+        } else {
+            Some(GCalTimestamp::from_date_time(
+                self.zone.resolve(self.local)?,
+            ))
+        }
+    }
+
+    /// Formats the time like Google does in the ids of instances, e.g.
+    /// `20210314T161500Z`.
+    fn id_suffix(&self) -> Option<String> {
+        Some(match self.key()? {
+            key if self.all_day => key.format("%Y%m%d").to_string(),
+            key => key.format("%Y%m%dT%H%M%SZ").to_string(),
+        })
+    }
+}
+
+/// E.g. `PT1H30M` or `P1D`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut value = value.strip_prefix('P')?;
+    let mut duration = Duration::zero();
+    let mut in_time = false;
+    while !value.is_empty() {
+        if let Some(rest) = value.strip_prefix('T') {
+            in_time = true;
+            value = rest;
+            continue;
+        }
+        let split = value.find(|c: char| !c.is_ascii_digit())?;
+        // Large enough for any real duration, small enough not to overflow.
+        let n: i64 = value[..split].parse().ok().filter(|n| *n < 1_000_000)?;
+        let unit = value[split..].chars().next()?;
+        duration = duration
+            + match (unit, in_time) {
+                ('W', false) => Duration::weeks(n),
+                ('D', false) => Duration::days(n),
+                ('H', true) => Duration::hours(n),
+                ('M', true) => Duration::minutes(n),
+                ('S', true) => Duration::seconds(n),
+                _ => return None,
+            };
+        value = &value[split + unit.len_utf8()..];
+    }
+    Some(if negative { -duration } else { duration })
+}
+
+/// A `VEVENT` before recurrences are expanded.
+#[derive(Debug)]
+struct Component {
+    properties: Vec<Property>,
+}
+
+impl Component {
+    fn get(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|property| property.name == name)
+    }
+
+    fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> + 'a {
+        self.properties
+            .iter()
+            .filter(move |property| property.name == name)
+    }
+
+    fn text(&self, name: &str) -> Option<String> {
+        self.get(name).map(Property::text)
+    }
+
+    fn time(&self, name: &str) -> Option<Time> {
+        self.get(name).and_then(Time::from_property)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.get("STATUS")
+            .is_some_and(|status| status.value.eq_ignore_ascii_case("CANCELLED"))
+    }
+
+    /// The start of every instance, without the ones that were removed with
+    /// `EXDATE`.
+    fn instances(&self, start: Time, window_end: DateTime<Utc>) -> Vec<Time> {
+        let rule = match self.get("RRULE").map(|rule| rule.value.parse::<RRule>()) {
+            Some(Ok(rule)) => Some(rule),
+            Some(Err(e)) => {
+                println!("ics: ignoring recurrence rule: {}", e);
+                None
+            }
+            None => None,
+        };
+        let mut instances: Vec<Time> = match rule {
+            Some(rule) => {
+                let until = rule
+                    .until()
+                    .and_then(|until| Time::parse(until, None))
+                    .map(|until| match until.zone {
+                        Zone::Utc => start.zone.local(Utc.from_utc_datetime(&until.local)),
+                        _ => until.local,
+                    });
+                rule.instances(start.local, until, start.zone.local(window_end))
+                    .into_iter()
+                    .map(|local| start.at(local))
+                    .collect()
+            }
+            None => vec![start],
+        };
+
+        let tzid = start.tzid();
+        instances.extend(self.all("RDATE").flat_map(|rdate| {
+            let tzid = rdate.param("TZID").or(tzid);
+            rdate
+                .value
+                .split(',')
+                .filter_map(move |value| Time::parse(value, tzid))
+                .collect::<Vec<_>>()
+        }));
+
+        let excluded: HashSet<_> = self
+            .all("EXDATE")
+            .flat_map(|exdate| {
+                let tzid = exdate.param("TZID").or(tzid);
+                exdate
+                    .value
+                    .split(',')
+                    .filter_map(move |value| Time::parse(value, tzid)?.key())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        instances.retain(|instance| instance.key().is_some_and(|key| !excluded.contains(&key)));
+        instances
+    }
+
+    /// Turns the component into an event starting at `start`, which is the
+    /// instance `instance_of` of a recurring event if set.
+    fn to_event(
+        &self,
+        uid: &str,
+        start: Time,
+        duration: Duration,
+        instance_of: Option<Time>,
+    ) -> Option<Event> {
+        let end = start.at(start.local + duration);
+        let id = match instance_of {
+            Some(instance) => format!("{}_{}", uid, instance.id_suffix()?),
+            None => uid.to_string(),
+        };
+        Some(
+            Event::new(
+                id,
+                self.text("SUMMARY").unwrap_or_default(),
+                start.to_timestamp()?,
+                end.to_timestamp()?,
+            )
+            .with_location(self.text("LOCATION"))
+            .with_description(self.text("DESCRIPTION"))
+            .with_recurring_event_id(instance_of.map(|_| uid.to_string()))
+            .with_original_start_time(match instance_of {
+                Some(instance) => Some(instance.to_timestamp()?),
+                None => None,
+            }),
+        )
+    }
+
+    /// How long the event is, from `DTEND` or `DURATION`.
+    fn duration(&self, start: Time) -> Duration {
+        if let Some(end) = self.time("DTEND") {
+            // Timed events can start and end in different time zones.
+            match (start.zone.resolve(start.local), end.zone.resolve(end.local)) {
+                (Some(from), Some(to)) if !start.all_day && !end.all_day => to - from,
+                _ => end.local - start.local,
+            }
+        } else if let Some(duration) = self
+            .get("DURATION")
+            .and_then(|duration| parse_duration(&duration.value))
+        {
+            duration
+        } else if start.all_day {
+            Duration::days(1)
+        } else {
+            Duration::zero()
+        }
+    }
+}
+
+/// Reads every event in an iCalendar file, with recurring events expanded up
+/// to a year from now.
+fn parse(text: &str) -> Result<Vec<Event>, ParseError> {
+    let mut components = Vec::new();
+    let mut stack = Vec::new();
+    let mut properties = Vec::new();
+    for line in unfold(text) {
+        let property = Property::parse(&line)?;
+        match property.name.as_str() {
+            "BEGIN" => stack.push(property.value.to_ascii_uppercase()),
+            "END" => {
+                let ended = stack.pop();
+                if ended.as_deref() == Some("VEVENT") {
+                    components.push(Component {
+                        properties: std::mem::take(&mut properties),
+                    });
+                }
+            }
+            // Properties of alarms and the like inside events are ignored.
+            _ if stack.last().map(String::as_str) == Some("VEVENT") => properties.push(property),
+            _ => (),
+        }
+    }
+
+    let window_end = Utc::now() + Duration::days(LOOK_AHEAD_DAYS);
+
+    // Instances of recurring events that were changed are separate events
+    // with the same UID and a RECURRENCE-ID.
+    let mut overrides: HashMap<(String, NaiveDateTime), &Component> = HashMap::new();
+    for component in &components {
+        if let (Some(uid), Some(key)) = (
+            component.get("UID"),
+            component.time("RECURRENCE-ID").and_then(|time| time.key()),
+        ) {
+            overrides.insert((uid.value.clone(), key), component);
+        }
+    }
+
+    let mut events = Vec::new();
+    for component in &components {
+        let (uid, start) = match (component.get("UID"), component.time("DTSTART")) {
+            (Some(uid), Some(start)) => (&uid.value, start),
+            _ => continue,
+        };
+        if component.get("RECURRENCE-ID").is_some() || component.is_cancelled() {
+            continue;
+        }
+        let duration = component.duration(start);
+        if component.get("RRULE").is_none() && component.get("RDATE").is_none() {
+            events.extend(component.to_event(uid, start, duration, None));
+            continue;
+        }
+
+        for instance in component.instances(start, window_end) {
+            let key = match instance.key() {
+                Some(key) => key,
+                None => continue,
+            };
+            let event = match overrides.get(&(uid.clone(), key)) {
+                Some(changed) if changed.is_cancelled() => None,
+                Some(changed) => changed.time("DTSTART").and_then(|start| {
+                    changed.to_event(uid, start, changed.duration(start), Some(instance))
+                }),
+                None => component.to_event(uid, instance, duration, Some(instance)),
+            };
+            events.extend(event);
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calendar::model::Timestamp, test_util::utc};
+    use std::convert::TryFrom;
+
+    fn component(text: &str) -> Component {
+        Component {
+            properties: unfold(text)
+                .iter()
+                .map(|line| Property::parse(line).unwrap())
+                .collect(),
+        }
+    }
+
+    fn calendar(events: &[&str]) -> String {
+        let events: String = events
+            .iter()
+            .map(|event| format!("BEGIN:VEVENT\r\n{}\r\nEND:VEVENT\r\n", event.trim()))
+            .collect();
+        format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{}END:VCALENDAR\r\n",
+            events
+        )
+    }
+
+    fn start_of(event: &Event) -> DateTime<Utc> {
+        event.start().instant().unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1D"), Some(Duration::days(1)));
+        assert_eq!(parse_duration("P1DT12H"), Some(Duration::hours(36)));
+        assert_eq!(parse_duration("+P2W"), Some(Duration::weeks(2)));
+        assert_eq!(parse_duration("-PT15M"), Some(Duration::minutes(-15)));
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("PT1D"), None);
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(parse_duration("PT1Ö"), None);
+        assert_eq!(parse_duration("PTÖ"), None);
+        assert_eq!(parse_duration("P9999999999999D"), None);
+    }
+
+    #[test]
+    fn unfolding() {
+        let text = "SUMMARY:Styrelse\r\n mö\r\n\tte\r\n\r\nLOCATION:Café Java\r\n";
+        assert_eq!(unfold(text), ["SUMMARY:Styrelsemöte", "LOCATION:Café Java"]);
+    }
+
+    #[test]
+    fn properties() {
+        let property =
+            Property::parse(r#"LOCATION;ALTREP="http://example.com/a:b";LANGUAGE=sv:A\, B\nC"#)
+                .unwrap();
+        assert_eq!(property.name, "LOCATION");
+        assert_eq!(property.param("ALTREP"), Some("http://example.com/a:b"));
+        assert_eq!(property.param("LANGUAGE"), Some("sv"));
+        assert_eq!(property.text(), "A, B\nC");
+        assert!(Property::parse("SUMMARY").is_err());
+    }
+
+    #[test]
+    fn time_zones() {
+        let events = parse(&calendar(&[
+            "UID:stockholm\r\nDTSTART;TZID=Europe/Stockholm:20300601T180000",
+            "UID:mozilla\r\nDTSTART;TZID=/mozilla.org/20050126_1/America/New_York:20300101T120000",
+            "UID:utc\r\nDTSTART:20300101T120000Z\r\nDURATION:PT2H",
+            "UID:floating\r\nDTSTART:20300101T120000",
+            "UID:all-day\r\nDTSTART;VALUE=DATE:20300101",
+        ]))
+        .unwrap();
+        let event = |id| events.iter().find(|event| event.id() == id).unwrap();
+
+        assert_eq!(start_of(event("stockholm")), utc("2030-06-01T16:00:00Z"));
+        assert_eq!(start_of(event("mozilla")), utc("2030-01-01T17:00:00Z"));
+        assert_eq!(start_of(event("utc")), utc("2030-01-01T12:00:00Z"));
+        assert_eq!(
+            event("utc").end().instant().unwrap().with_timezone(&Utc),
+            utc("2030-01-01T14:00:00Z")
+        );
+        // Floating times are in the configured time zone.
+        assert_eq!(start_of(event("floating")), utc("2030-01-01T11:00:00Z"));
+        assert!(matches!(
+            Timestamp::try_from(event("all-day").start()),
+            Ok(Timestamp::Date(date)) if date == NaiveDate::from_ymd(2030, 1, 1)
+        ));
+        assert!(matches!(
+            Timestamp::try_from(event("all-day").end()),
+            Ok(Timestamp::Date(date)) if date == NaiveDate::from_ymd(2030, 1, 2)
+        ));
+    }
+
+    #[test]
+    fn utc_until_with_time_zone() {
+        // 16:00 UTC is 18:00 in Stockholm after the switch to summer time, so
+        // the last instance is included even though 16 < 18.
+        let component = component(
+            "DTSTART;TZID=Europe/Stockholm:20240325T180000\r\n\
+             RRULE:FREQ=WEEKLY;UNTIL=20240408T160000Z",
+        );
+        let start = component.time("DTSTART").unwrap();
+        let instances = component.instances(start, utc("2030-01-01T00:00:00Z"));
+        let starts: Vec<_> = instances
+            .iter()
+            .map(|instance| instance.to_timestamp().unwrap())
+            .map(|timestamp| timestamp.instant().unwrap().with_timezone(&Utc))
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                utc("2024-03-25T17:00:00Z"),
+                utc("2024-04-01T16:00:00Z"),
+                utc("2024-04-08T16:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn exdate() {
+        let component = component(
+            "DTSTART;TZID=Europe/Stockholm:20240101T180000\r\n\
+             RRULE:FREQ=WEEKLY;COUNT=4\r\n\
+             EXDATE;TZID=Europe/Stockholm:20240108T180000\r\n\
+             EXDATE:20240122T170000Z",
+        );
+        let start = component.time("DTSTART").unwrap();
+        let instances = component.instances(start, utc("2030-01-01T00:00:00Z"));
+        let dates: Vec<_> = instances
+            .iter()
+            .map(|instance| instance.local.date().to_string())
+            .collect();
+        assert_eq!(dates, ["2024-01-01", "2024-01-15"]);
+    }
+
+    #[test]
+    fn overrides() {
+        // Recurring events are only expanded a year ahead, so they can't be
+        // at a fixed date.
+        let first = (Utc::now() + Duration::days(7)).date().naive_utc();
+        let day = |weeks| {
+            (first + Duration::weeks(weeks))
+                .format("%Y%m%d")
+                .to_string()
+        };
+        let events = parse(&calendar(&[
+            &format!(
+                "UID:meeting\r\nSUMMARY:Möte\r\nDTSTART;TZID=Europe/Stockholm:{}T180000\r\n\
+                 DURATION:PT1H\r\nRRULE:FREQ=WEEKLY;COUNT=3",
+                day(0)
+            ),
+            &format!(
+                "UID:meeting\r\nSUMMARY:Flyttat möte\r\n\
+                 RECURRENCE-ID;TZID=Europe/Stockholm:{0}T180000\r\n\
+                 DTSTART;TZID=Europe/Stockholm:{0}T190000\r\n\
+                 DTEND;TZID=Europe/Stockholm:{0}T200000",
+                day(1)
+            ),
+            &format!(
+                "UID:meeting\r\nSTATUS:CANCELLED\r\n\
+                 RECURRENCE-ID;TZID=Europe/Stockholm:{0}T180000\r\n\
+                 DTSTART;TZID=Europe/Stockholm:{0}T180000",
+                day(2)
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].summary(), "Möte");
+        let moved = &events[1];
+        assert_eq!(moved.summary(), "Flyttat möte");
+        assert_eq!(moved.recurring_event_id().as_deref(), Some("meeting"));
+        let local = |event: &Event| event.start().instant().unwrap().naive_local();
+        assert_eq!(local(moved).format("%H:%M").to_string(), "19:00");
+        // The id and original start are those of the instance it replaces.
+        let original = moved.original_start_time().as_ref().unwrap();
+        assert_eq!(
+            original
+                .instant()
+                .unwrap()
+                .naive_local()
+                .format("%H:%M")
+                .to_string(),
+            "18:00"
+        );
+        let original = original.instant().unwrap().with_timezone(&Utc);
+        assert_eq!(
+            moved.id(),
+            format!("meeting_{}", original.format("%Y%m%dT%H%M%SZ"))
+        );
+    }
+}
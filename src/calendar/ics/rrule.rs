@@ -0,0 +1,494 @@
+//! Recurrence rules, see RFC 5545 section 3.3.10.
+//!
+//! Only the parts that calendar programs use for human events are supported:
+//! `FREQ` from `DAILY` to `YEARLY`, `INTERVAL`, `COUNT`, `UNTIL`, `BYDAY`,
+//! `BYMONTHDAY`, `BYMONTH`, `BYSETPOS` and `WKST`. Rules with other parts are
+//! rejected rather than expanded wrongly.
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use std::{convert::TryFrom, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone)]
+pub struct RRule {
+    freq: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    /// Parsed by the caller since it depends on the time zone of `DTSTART`.
+    until: Option<String>,
+    /// The weekdays, optionally with which occurence in the month or year,
+    /// e.g. `-1FR` for the last Friday.
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+    by_set_pos: Vec<i32>,
+    week_start: Weekday,
+}
+
+impl RRule {
+    pub fn until(&self) -> Option<&str> {
+        self.until.as_deref()
+    }
+
+    /// The start of every instance of the rule up to and including `end`,
+    /// beginning with `start`. All times are wall clock times in the time zone
+    /// of `start`, and `until` has to be converted to it.
+    pub fn instances(
+        &self,
+        start: NaiveDateTime,
+        until: Option<NaiveDateTime>,
+        end: NaiveDateTime,
+    ) -> Vec<NaiveDateTime> {
+        let mut instances = vec![start];
+        for period in 0.. {
+            let (period_start, dates) = match self.dates_in_period(start.date(), period) {
+                Some(period) => period,
+                None => break,
+            };
+            if period_start > end.date() {
+                break;
+            }
+            for date in dates {
+                let instance = date.and_time(start.time());
+                if instance <= start {
+                    continue;
+                }
+                if instance > end
+                    || until.is_some_and(|until| instance > until)
+                    || self
+                        .count
+                        .is_some_and(|count| instances.len() >= count as usize)
+                {
+                    return instances;
+                }
+                instances.push(instance);
+            }
+        }
+        instances
+    }
+
+    /// The first day of the `n`th period after the one `start` is in, and the
+    /// dates in it that match the rule, in order. Returns `None` if the
+    /// period is out of range.
+    fn dates_in_period(&self, start: NaiveDate, n: u32) -> Option<(NaiveDate, Vec<NaiveDate>)> {
+        let steps = i64::from(n) * i64::from(self.interval);
+        let (period_start, mut dates) = match self.freq {
+            Frequency::Daily => {
+                let date = start.checked_add_signed(days(steps)?)?;
+                let dates = if self.matches_day(date) {
+                    vec![date]
+                } else {
+                    Vec::new()
+                };
+                (date, dates)
+            }
+            Frequency::Weekly => {
+                let week_start = start
+                    .checked_sub_signed(Duration::days(days_between(
+                        self.week_start,
+                        start.weekday(),
+                    )))?
+                    .checked_add_signed(days(steps.checked_mul(7)?)?)?;
+                let weekdays = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|(_, weekday)| *weekday).collect()
+                };
+                let dates = weekdays
+                    .into_iter()
+                    .filter_map(|weekday| {
+                        week_start.checked_add_signed(Duration::days(days_between(
+                            self.week_start,
+                            weekday,
+                        )))
+                    })
+                    .collect();
+                (week_start, dates)
+            }
+            Frequency::Monthly => {
+                let months = i64::from(start.month0()) + steps;
+                let year = start.year().checked_add(i32::try_from(months / 12).ok()?)?;
+                let month = (months % 12) as u32 + 1;
+                let period_start = NaiveDate::from_ymd_opt(year, month, 1)?;
+                (period_start, self.dates_in_month(year, month, start.day()))
+            }
+            Frequency::Yearly => {
+                let year = start.year().checked_add(i32::try_from(steps).ok()?)?;
+                let period_start = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                let dates = if !self.by_month.is_empty() {
+                    self.by_month
+                        .iter()
+                        .flat_map(|month| self.dates_in_month(year, *month, start.day()))
+                        .collect()
+                } else if !self.by_month_day.is_empty() {
+                    (1..=12)
+                        .flat_map(|month| self.dates_in_month(year, month, start.day()))
+                        .collect()
+                } else if !self.by_day.is_empty() {
+                    let days = days_in_year(year);
+                    (0..days)
+                        .filter_map(|day| {
+                            period_start.checked_add_signed(Duration::days(day.into()))
+                        })
+                        .filter(|date| self.matches_weekday(*date, date.ordinal0(), days))
+                        .collect()
+                } else {
+                    NaiveDate::from_ymd_opt(year, start.month(), start.day())
+                        .into_iter()
+                        .collect()
+                };
+                (period_start, dates)
+            }
+        };
+
+        dates.retain(|date| self.by_month.is_empty() || self.by_month.contains(&date.month()));
+        dates.sort_unstable();
+        dates.dedup();
+        if !self.by_set_pos.is_empty() {
+            let len = dates.len() as i32;
+            dates = self
+                .by_set_pos
+                .iter()
+                .filter_map(|pos| {
+                    let index = if *pos < 0 { len + pos } else { pos - 1 };
+                    dates.get(usize::try_from(index).ok()?).copied()
+                })
+                .collect();
+            dates.sort_unstable();
+        }
+        Some((period_start, dates))
+    }
+
+    /// The dates in a month that match `BYMONTHDAY` and `BYDAY`, or the same
+    /// day as the start if neither is set.
+    fn dates_in_month(&self, year: i32, month: u32, start_day: u32) -> Vec<NaiveDate> {
+        let days = days_in_month(year, month);
+        let mut dates: Vec<_> = if !self.by_month_day.is_empty() {
+            self.by_month_day
+                .iter()
+                .filter_map(|day| {
+                    let day = if *day < 0 {
+                        days as i32 + day + 1
+                    } else {
+                        *day
+                    };
+                    NaiveDate::from_ymd_opt(year, month, u32::try_from(day).ok()?)
+                })
+                .collect()
+        } else if !self.by_day.is_empty() {
+            (1..=days)
+                .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                .collect()
+        } else {
+            NaiveDate::from_ymd_opt(year, month, start_day)
+                .into_iter()
+                .collect()
+        };
+        if !self.by_day.is_empty() {
+            dates.retain(|date| self.matches_weekday(*date, date.day0(), days));
+        }
+        dates
+    }
+
+    /// Whether `date` matches the `BY*` parts for a daily rule.
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let days = days_in_month(date.year(), date.month()) as i32;
+        (self.by_month_day.is_empty()
+            || self.by_month_day.iter().any(|day| {
+                *day == date.day() as i32 || (*day < 0 && days + day + 1 == date.day() as i32)
+            }))
+            && (self.by_day.is_empty()
+                || self
+                    .by_day
+                    .iter()
+                    .any(|(_, weekday)| *weekday == date.weekday()))
+    }
+
+    /// Whether `date` matches `BYDAY`, where `index` is the zero-based index
+    /// of the date within the month or year of `len` days that the ordinals
+    /// refer to.
+    fn matches_weekday(&self, date: NaiveDate, index: u32, len: u32) -> bool {
+        self.by_day.iter().any(|(ordinal, weekday)| {
+            *weekday == date.weekday()
+                && match ordinal {
+                    None => true,
+                    Some(n) if *n > 0 => (index / 7 + 1) as i32 == *n,
+                    Some(n) => ((len - 1 - index) / 7 + 1) as i32 == -n,
+                }
+        })
+    }
+}
+
+impl FromStr for RRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut freq = None;
+        let mut rule = RRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+            week_start: Weekday::Mon,
+        };
+
+        for part in s.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rule part {}", part))?;
+            let invalid = || format!("invalid {} {}", name, value);
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("unsupported frequency {}", value)),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = value.parse().map_err(|_| invalid())?;
+                    if rule.interval == 0 {
+                        return Err(invalid());
+                    }
+                }
+                "COUNT" => rule.count = Some(value.parse().map_err(|_| invalid())?),
+                "UNTIL" => rule.until = Some(value.to_string()),
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(|day| parse_by_day(day).ok_or_else(invalid))
+                        .collect::<Result<_, _>>()?
+                }
+                "BYMONTHDAY" => rule.by_month_day = parse_list(value).ok_or_else(invalid)?,
+                "BYMONTH" => rule.by_month = parse_list(value).ok_or_else(invalid)?,
+                "BYSETPOS" => rule.by_set_pos = parse_list(value).ok_or_else(invalid)?,
+                "WKST" => rule.week_start = parse_weekday(value).ok_or_else(invalid)?,
+                _ => return Err(format!("unsupported rule part {}", name)),
+            }
+        }
+
+        rule.freq = freq.ok_or_else(|| "missing FREQ".to_string())?;
+        Ok(rule)
+    }
+}
+
+fn parse_list<T: FromStr>(value: &str) -> Option<Vec<T>> {
+    value.split(',').map(|n| n.parse().ok()).collect()
+}
+
+/// E.g. `MO`, `2TU` or `-1FR`.
+fn parse_by_day(s: &str) -> Option<(Option<i32>, Weekday)> {
+    let split = s.len().checked_sub(2)?;
+    if !s.is_char_boundary(split) {
+        return None;
+    }
+    // There are at most 53 of a weekday in a year, and 0 means nothing.
+    let ordinal = match &s[..split] {
+        "" => None,
+        n => Some(
+            n.parse::<i32>()
+                .ok()
+                .filter(|n| *n != 0 && (-53..=53).contains(n))?,
+        ),
+    };
+    Some((ordinal, parse_weekday(&s[split..])?))
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    match s.to_ascii_uppercase().as_str() {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// Like `Duration::days`, which panics if `days` is out of range.
+fn days(days: i64) -> Option<Duration> {
+    (days.checked_abs()? <= i64::MAX / 1000 / 86_400).then(|| Duration::days(days))
+}
+
+/// How many days after `from` the next `to` is, between 0 and 6.
+fn days_between(from: Weekday, to: Weekday) -> i64 {
+    (i64::from(to.num_days_from_monday()) - i64::from(from.num_days_from_monday())).rem_euclid(7)
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    (28..=31)
+        .rev()
+        .find(|day| NaiveDate::from_ymd_opt(year, month, *day).is_some())
+        .unwrap_or(28)
+}
+
+fn days_in_year(year: i32) -> u32 {
+    if NaiveDate::from_ymd_opt(year, 2, 29).is_some() {
+        366
+    } else {
+        365
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{}T18:00:00", date), "%Y-%m-%dT%H:%M:%S").unwrap()
+    }
+
+    fn expand(rule: &str, start: &str) -> Vec<String> {
+        rule.parse::<RRule>()
+            .unwrap()
+            .instances(at(start), None, at("2030-01-01"))
+            .into_iter()
+            .map(|instance| instance.date().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn weekly() {
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4", "2024-01-01"),
+            ["2024-01-01", "2024-01-03", "2024-01-08", "2024-01-10"],
+        );
+        assert_eq!(
+            expand("FREQ=WEEKLY;INTERVAL=2;COUNT=3", "2024-01-01"),
+            ["2024-01-01", "2024-01-15", "2024-01-29"],
+        );
+    }
+
+    #[test]
+    fn week_start() {
+        // With weeks starting on Sunday, the Sunday after the start is in the
+        // skipped week.
+        assert_eq!(
+            expand(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,SU;WKST=SU;COUNT=4",
+                "1997-08-05"
+            ),
+            ["1997-08-05", "1997-08-17", "1997-08-19", "1997-08-31"],
+        );
+        assert_eq!(
+            expand(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,SU;WKST=MO;COUNT=4",
+                "1997-08-05"
+            ),
+            ["1997-08-05", "1997-08-10", "1997-08-19", "1997-08-24"],
+        );
+    }
+
+    #[test]
+    fn monthly_by_day() {
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYDAY=2TU;COUNT=3", "2024-01-09"),
+            ["2024-01-09", "2024-02-13", "2024-03-12"],
+        );
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYDAY=-1FR;COUNT=3", "2024-01-26"),
+            ["2024-01-26", "2024-02-23", "2024-03-29"],
+        );
+    }
+
+    #[test]
+    fn by_set_pos() {
+        // The last weekday of the month.
+        assert_eq!(
+            expand(
+                "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1;COUNT=3",
+                "2024-01-31"
+            ),
+            ["2024-01-31", "2024-02-29", "2024-03-29"],
+        );
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYDAY=SA,SU;BYSETPOS=1;COUNT=3", "2024-01-06"),
+            ["2024-01-06", "2024-02-03", "2024-03-02"],
+        );
+    }
+
+    #[test]
+    fn day_31_in_short_months() {
+        // Months without the day are skipped, not moved.
+        assert_eq!(
+            expand("FREQ=MONTHLY;COUNT=4", "2024-01-31"),
+            ["2024-01-31", "2024-03-31", "2024-05-31", "2024-07-31"],
+        );
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYMONTHDAY=-1;COUNT=3", "2024-01-31"),
+            ["2024-01-31", "2024-02-29", "2024-03-31"],
+        );
+        assert_eq!(
+            expand("FREQ=YEARLY;COUNT=2", "2024-02-29"),
+            ["2024-02-29", "2028-02-29"],
+        );
+    }
+
+    #[test]
+    fn yearly_by_month_and_day() {
+        // When summer time starts in the EU.
+        assert_eq!(
+            expand("FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU;COUNT=3", "2024-03-31"),
+            ["2024-03-31", "2025-03-30", "2026-03-29"],
+        );
+    }
+
+    #[test]
+    fn until_and_end() {
+        let rule = "FREQ=DAILY".parse::<RRule>().unwrap();
+        // Both are inclusive.
+        let instances = rule.instances(at("2024-01-01"), Some(at("2024-01-03")), at("2030-01-01"));
+        assert_eq!(instances.len(), 3);
+        let instances = rule.instances(at("2024-01-01"), None, at("2024-01-05"));
+        assert_eq!(instances.len(), 5);
+    }
+
+    #[test]
+    fn huge_intervals() {
+        for rule in &[
+            "FREQ=DAILY;INTERVAL=4294967295",
+            "FREQ=WEEKLY;INTERVAL=4294967295",
+            "FREQ=MONTHLY;INTERVAL=4294967295",
+            "FREQ=YEARLY;INTERVAL=4294967295",
+        ] {
+            let rule = rule.parse::<RRule>().unwrap();
+            let end = chrono::naive::MAX_DATE.and_hms(0, 0, 0);
+            assert_eq!(rule.instances(at("2024-01-01"), None, end).len(), 1);
+        }
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for rule in &[
+            "BYDAY=MO",
+            "FREQ=SECONDLY",
+            "FREQ=DAILY;BYHOUR=10",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=WEEKLY;BYDAY=XX",
+            "FREQ=WEEKLY;BYDAY=Ö",
+            "FREQ=WEEKLY;BYDAY=1ÖÖ",
+            "FREQ=WEEKLY;BYDAY=ÖMO",
+            "FREQ=MONTHLY;BYDAY=0TU",
+            "FREQ=MONTHLY;BYDAY=54TU",
+            "FREQ=MONTHLY;BYDAY=-54TU",
+            "FREQ=MONTHLY;BYDAY=-2147483648TU",
+        ] {
+            assert!(rule.parse::<RRule>().is_err(), "{}", rule);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Looking up and changing meetings in the calendar, for commands.
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use futures_util::{pin_mut, TryStreamExt};
+use std::convert::TryInto;
+use tokio::sync::mpsc;
+
+use crate::{
+    calendar::{model::Timestamp, CalendarClient, Request},
+    config::{self, MeetingMatcher},
+    error::RequestError,
+};
+
+use super::model::{
+    events::{
+        Event, EventBody, EventsDeleteRequest, EventsInsertRequest, EventsListRequest,
+        EventsPatchRequest,
+    },
+    GCalTimestamp,
+};
+
+/// How far ahead to look for meetings.
+const LOOK_AHEAD_DAYS: i64 = 365;
+
+/// Finds the first meeting matching `matcher` that starts in `from..until`,
+/// or within a year if `until` is `None`. Returns the event and its start.
+pub async fn find(
+    client: &CalendarClient,
+    matcher: &MeetingMatcher,
+    from: DateTime<Tz>,
+    until: Option<DateTime<Tz>>,
+) -> Result<Option<(Event, DateTime<Utc>)>, RequestError> {
+    let until = until.unwrap_or_else(|| from + Duration::days(LOOK_AHEAD_DAYS));
+    let pages = EventsListRequest::new(client.calendar_id().to_string())
+        .order_by("startTime".to_string())
+        .single_events(true)
+        .time_zone(config::time_zone().name().to_string())
+        .time_min(from)
+        .time_max(until)
+        .pages(client);
+    pin_mut!(pages);
+
+    let from = from.with_timezone(&Utc);
+    while let Some(page) = pages.try_next().await? {
+        let meeting = page.into_items().into_iter().find_map(|event| {
+            let start = matcher.start_of(&event)?;
+            (start >= from).then_some((event, start))
+        });
+        if meeting.is_some() {
+            return Ok(meeting);
+        }
+    }
+    Ok(None)
+}
+
+/// Creates a new meeting of the kind `matcher` describes.
+///
+/// Changes are announced by the calendar task unless they are expected, so
+/// this and the functions below tell it about them on `requests`.
+pub async fn schedule(
+    client: &CalendarClient,
+    requests: &mpsc::UnboundedSender<Request>,
+    matcher: &MeetingMatcher,
+    start: DateTime<Tz>,
+    location: Option<String>,
+) -> Result<Event, RequestError> {
+    let body = EventBody::new()
+        .summary(matcher.event_summary())
+        .location(location)
+        .start(GCalTimestamp::from_date_time(start))
+        .end(GCalTimestamp::from_date_time(
+            start + Duration::minutes(matcher.length),
+        ));
+    let event = EventsInsertRequest::new(client.calendar_id().to_string(), body)
+        .request(client)
+        .await?;
+    expect_change(requests, event.id());
+    Ok(event)
+}
+
+/// Moves `meeting` so that it starts at `start`, keeping its length.
+pub async fn reschedule(
+    client: &CalendarClient,
+    requests: &mpsc::UnboundedSender<Request>,
+    matcher: &MeetingMatcher,
+    meeting: &Event,
+    start: DateTime<Tz>,
+) -> Result<Event, RequestError> {
+    let length = match (meeting.start().try_into(), meeting.end().try_into()) {
+        (Ok(Timestamp::DateTime(old_start)), Ok(Timestamp::DateTime(old_end))) => {
+            old_end - old_start
+        }
+        _ => Duration::minutes(matcher.length),
+    };
+    let body = EventBody::new()
+        .start(GCalTimestamp::from_date_time(start))
+        .end(GCalTimestamp::from_date_time(start + length));
+    // Before the request, since a sync could see the change before we return.
+    expect_change(requests, meeting.id());
+    EventsPatchRequest::new(
+        client.calendar_id().to_string(),
+        meeting.id().to_string(),
+        body,
+    )
+    .request(client)
+    .await
+}
+
+/// Removes `meeting` from the calendar.
+pub async fn cancel(
+    client: &CalendarClient,
+    requests: &mpsc::UnboundedSender<Request>,
+    meeting: &Event,
+) -> Result<(), RequestError> {
+    expect_change(requests, meeting.id());
+    EventsDeleteRequest::new(client.calendar_id().to_string(), meeting.id().to_string())
+        .request(client)
+        .await
+}
+
+fn expect_change(requests: &mpsc::UnboundedSender<Request>, id: &str) {
+    // If the calendar task is gone, there is nobody to announce it anyway.
+    let _ = requests.send(Request::ExpectChange(id.to_string()));
+}
@@ -1,9 +1,13 @@
 //! Rust-representations of common Google Calendar API types.
 
-use chrono::FixedOffset;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 
+use crate::config;
+
+pub mod channels;
 pub mod events;
 
 #[macro_export]
@@ -31,15 +35,72 @@ macro_rules! impl_get {
     };
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GCalTimestamp {
+    #[serde(skip_serializing_if = "Option::is_none")]
     date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     date_time: Option<String>,
+    /// The IANA time zone the time is specified in, e.g. `Europe/Stockholm`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_zone: Option<String>,
 }
 
 impl GCalTimestamp {
-    impl_get!(date: &Option<String>, date_time: &Option<String>,);
+    impl_get!(
+        date: &Option<String>,
+        date_time: &Option<String>,
+        time_zone: &Option<String>,
+    );
+
+    /// A timestamp for an event with a start time. The time zone is kept so
+    /// that recurring events keep their wall clock time over DST changes.
+    pub fn from_date_time(date_time: DateTime<Tz>) -> Self {
+        Self {
+            date: None,
+            date_time: Some(date_time.to_rfc3339()),
+            time_zone: Some(date_time.timezone().name().to_string()),
+        }
+    }
+
+    /// A timestamp for an all-day event.
+    pub fn from_date(date: NaiveDate) -> Self {
+        Self {
+            date: Some(date.format("%Y-%m-%d").to_string()),
+            date_time: None,
+            time_zone: None,
+        }
+    }
+
+    /// The time zone of the event, or the configured one if it doesn't have
+    /// one.
+    pub fn zone(&self) -> Tz {
+        self.time_zone
+            .as_deref()
+            .and_then(|time_zone| time_zone.parse().ok())
+            .unwrap_or_else(config::time_zone)
+    }
+
+    /// When the timestamp is, in the configured time zone. All-day
+    /// timestamps are at midnight in the event's time zone.
+    pub fn instant(&self) -> Option<DateTime<Tz>> {
+        let instant = match Timestamp::try_from(self).ok()? {
+            Timestamp::DateTime(date_time) => return Some(date_time),
+            Timestamp::Date(date) => resolve_local(self.zone(), date.and_hms(0, 0, 0))?,
+        };
+        Some(instant.with_timezone(&config::time_zone()))
+    }
+}
+
+/// The instant a wall clock time in `tz` refers to. The earlier one is picked
+/// when the clock is turned back, and times that are skipped when it is
+/// turned forward are moved forward an hour.
+pub fn resolve_local(tz: Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    tz.from_local_datetime(&time).earliest().or_else(|| {
+        tz.from_local_datetime(&(time + Duration::hours(1)))
+            .earliest()
+    })
 }
 
 impl TryFrom<&GCalTimestamp> for Timestamp {
@@ -47,12 +108,21 @@ impl TryFrom<&GCalTimestamp> for Timestamp {
 
     fn try_from(value: &GCalTimestamp) -> Result<Self, Self::Error> {
         if let Some(date_time) = &value.date_time {
-            Ok(Timestamp::DateTime(
-                chrono::DateTime::parse_from_rfc3339(&date_time).map_err(|_| ())?,
-            ))
+            let date_time = match DateTime::parse_from_rfc3339(date_time) {
+                Ok(date_time) => date_time.with_timezone(&config::time_zone()),
+                // The offset may be left out if the time zone is given.
+                Err(_) => resolve_local(
+                    value.zone(),
+                    NaiveDateTime::parse_from_str(date_time, "%Y-%m-%dT%H:%M:%S")
+                        .map_err(|_| ())?,
+                )
+                .ok_or(())?
+                .with_timezone(&config::time_zone()),
+            };
+            Ok(Timestamp::DateTime(date_time))
         } else if let Some(date) = &value.date {
             Ok(Timestamp::Date(
-                chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|_| ())?,
+                NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| ())?,
             ))
         } else {
             Err(())
@@ -68,27 +138,161 @@ impl TryFrom<GCalTimestamp> for Timestamp {
     }
 }
 
+/// A parsed [`GCalTimestamp`]. Times are in the configured time zone.
 #[derive(Debug)]
 pub enum Timestamp {
-    Date(chrono::NaiveDate),
-    DateTime(chrono::DateTime<FixedOffset>),
+    Date(NaiveDate),
+    DateTime(DateTime<Tz>),
 }
 
 impl Timestamp {
     //TODO macro this
 
     #[allow(dead_code)]
-    pub fn date(&self) -> Option<&chrono::NaiveDate> {
+    pub fn date(&self) -> Option<&NaiveDate> {
         match self {
             Timestamp::Date(date) => Some(date),
             _ => None,
         }
     }
 
-    pub fn date_time(&self) -> Option<&chrono::DateTime<FixedOffset>> {
+    pub fn date_time(&self) -> Option<&DateTime<Tz>> {
         match self {
             Timestamp::DateTime(date_time) => Some(date_time),
             _ => None,
         }
     }
 }
+
+/// The body of an unsuccessful response.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiErrorResponse {
+    pub error: ApiError,
+}
+
+/// See `https://developers.google.com/calendar/api/guides/errors`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiError {
+    code: u16,
+    message: String,
+    #[serde(default)]
+    errors: Vec<ApiErrorDetail>,
+    status: Option<String>,
+}
+
+impl ApiError {
+    impl_get!(
+        code: &u16,
+        message: &str,
+        errors: &[ApiErrorDetail],
+        status: &Option<String>,
+    );
+
+    /// Whether any of the errors has `reason`, e.g. `rateLimitExceeded`.
+    pub fn has_reason(&self, reason: &str) -> bool {
+        self.errors.iter().any(|error| error.reason == reason)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiErrorDetail {
+    domain: String,
+    reason: String,
+    message: String,
+}
+
+impl ApiErrorDetail {
+    impl_get!(domain: &str, reason: &str, message: &str);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::utc;
+    use chrono::Utc;
+
+    const STOCKHOLM: Tz = Tz::Europe__Stockholm;
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn timestamp(date: Option<&str>, date_time: Option<&str>, zone: Option<&str>) -> GCalTimestamp {
+        GCalTimestamp {
+            date: date.map(str::to_string),
+            date_time: date_time.map(str::to_string),
+            time_zone: zone.map(str::to_string),
+        }
+    }
+
+    fn instant(timestamp: &GCalTimestamp) -> DateTime<Utc> {
+        timestamp.instant().unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn resolve_local_spring_forward() {
+        let resolve = |s| {
+            resolve_local(STOCKHOLM, local(s))
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        assert_eq!(resolve("2024-03-31 01:30"), utc("2024-03-31T00:30:00Z"));
+        // 02:00-03:00 doesn't exist, so it's moved forward an hour.
+        assert_eq!(resolve("2024-03-31 02:00"), utc("2024-03-31T01:00:00Z"));
+        assert_eq!(resolve("2024-03-31 02:30"), utc("2024-03-31T01:30:00Z"));
+        assert_eq!(resolve("2024-03-31 03:00"), utc("2024-03-31T01:00:00Z"));
+    }
+
+    #[test]
+    fn resolve_local_fall_back() {
+        let resolve = |s| {
+            resolve_local(STOCKHOLM, local(s))
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        // 02:00-03:00 happens twice, and the first one is picked.
+        assert_eq!(resolve("2024-10-27 01:30"), utc("2024-10-26T23:30:00Z"));
+        assert_eq!(resolve("2024-10-27 02:30"), utc("2024-10-27T00:30:00Z"));
+        assert_eq!(resolve("2024-10-27 03:00"), utc("2024-10-27T02:00:00Z"));
+    }
+
+    #[test]
+    fn date_time_with_offset() {
+        // The offset wins over the time zone.
+        let start = timestamp(
+            None,
+            Some("2024-10-27T02:30:00+01:00"),
+            Some("Europe/Stockholm"),
+        );
+        assert_eq!(instant(&start), utc("2024-10-27T01:30:00Z"));
+    }
+
+    #[test]
+    fn date_time_without_offset() {
+        let start = timestamp(None, Some("2024-10-27T02:30:00"), Some("Europe/Stockholm"));
+        assert_eq!(instant(&start), utc("2024-10-27T00:30:00Z"));
+        let start = timestamp(None, Some("2024-03-31T02:30:00"), Some("Europe/Stockholm"));
+        assert_eq!(instant(&start), utc("2024-03-31T01:30:00Z"));
+        // The US switches on other days.
+        let start = timestamp(None, Some("2024-03-10T02:30:00"), Some("America/New_York"));
+        assert_eq!(instant(&start), utc("2024-03-10T07:30:00Z"));
+        // Without a time zone, the configured one is used.
+        let start = timestamp(None, Some("2024-03-31T12:00:00"), None);
+        assert_eq!(instant(&start), utc("2024-03-31T10:00:00Z"));
+        // And it's converted to the configured one.
+        assert_eq!(start.instant().unwrap().timezone(), config::time_zone());
+    }
+
+    #[test]
+    fn all_day_instant() {
+        let day = timestamp(Some("2024-03-31"), None, None);
+        assert_eq!(instant(&day), utc("2024-03-30T23:00:00Z"));
+        let day = timestamp(Some("2024-10-27"), None, None);
+        assert_eq!(instant(&day), utc("2024-10-26T22:00:00Z"));
+        let day = timestamp(Some("2024-04-01"), None, Some("America/New_York"));
+        assert_eq!(instant(&day), utc("2024-04-01T04:00:00Z"));
+        // Brazil used to switch at midnight, so the day started at 01:00.
+        let day = timestamp(Some("2018-11-04"), None, Some("America/Sao_Paulo"));
+        assert_eq!(instant(&day), utc("2018-11-04T03:00:00Z"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Rust-representations of the Channels Google Calendar API, used for push
+//! notifications.
+//!
+//! See `https://developers.google.com/calendar/api/v3/reference/channels`.
+
+use chrono::{DateTime, TimeZone, Utc};
+use hyper::Method;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use url::Url;
+
+use crate::{
+    calendar::{drain_body, CalendarClient},
+    error::{BodyParseError, RequestError},
+    impl_get,
+};
+
+/// A notification channel, both as sent to `watch` and as returned by it.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Channel {
+    id: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    channel_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resource_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resource_uri: Option<String>,
+    /// Milliseconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    params: HashMap<String, String>,
+}
+
+impl Channel {
+    impl_get!(
+        id: &str,
+        address: &Option<String>,
+        token: &Option<String>,
+        resource_id: &Option<String>,
+        resource_uri: &Option<String>,
+    );
+
+    /// A channel that posts notifications to `address`, which has to be an
+    /// HTTPS URL. `token` is sent along with every notification. The channel
+    /// lives for `ttl` seconds.
+    pub fn web_hook(id: String, address: String, token: String, ttl: u64) -> Self {
+        Self {
+            id,
+            channel_type: Some("web_hook".to_string()),
+            address: Some(address),
+            token: Some(token),
+            params: vec![("ttl".to_string(), ttl.to_string())]
+                .into_iter()
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// When Google stops sending notifications.
+    pub fn expiration(&self) -> Option<DateTime<Utc>> {
+        let millis = self.expiration.as_ref()?.parse().ok()?;
+        Utc.timestamp_millis_opt(millis).single()
+    }
+}
+
+/// Stops notifications for a channel.
+#[derive(Debug, Clone)]
+pub struct ChannelsStopRequest {
+    channel: Channel,
+}
+
+impl ChannelsStopRequest {
+    /// `channel` needs its id and resource id, as returned by `watch`.
+    pub fn new(channel: &Channel) -> Self {
+        Self {
+            channel: Channel {
+                id: channel.id.clone(),
+                resource_id: channel.resource_id.clone(),
+                ..Channel::default()
+            },
+        }
+    }
+
+    pub async fn request(self, client: &CalendarClient) -> Result<(), RequestError> {
+        let mut url = Url::parse(client.base_url())?;
+        url.path_segments_mut()
+            .map_err(|_| url::ParseError::RelativeUrlWithCannotBeABaseBase)?
+            .pop_if_empty()
+            .extend(&["channels", "stop"]);
+        let body = serde_json::to_vec(&self.channel)
+            .map_err(|e| RequestError::ResponseError(BodyParseError::JsonError(e)))?;
+        let body = client.request(Method::POST, &url, body).await?;
+        drain_body(body).await.map_err(RequestError::ResponseError)
+    }
+}
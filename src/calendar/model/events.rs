@@ -2,64 +2,91 @@
 //!
 //! See `https://developers.google.com/calendar/api/v3/reference/events`.
 
-use chrono::{DateTime, TimeZone};
-use hyper::Body;
-use serde::{Deserialize, Serialize};
-use std::fmt;
+use chrono::{DateTime, FixedOffset, SecondsFormat, TimeZone, Utc};
+use futures_util::stream::{self, Stream, TryStreamExt};
+use hyper::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, fmt, future::Future};
 use url::Url;
-use yup_oauth2::AccessToken;
 
 use crate::{
-    calendar::{parse_json_body, request},
-    error::RequestError,
+    calendar::{drain_body, parse_json_body, CalendarClient},
+    error::{BodyParseError, RequestError},
     impl_builder, impl_get,
 };
 
-use super::GCalTimestamp;
+use super::{channels::Channel, GCalTimestamp};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EventsListRequest {
     calendar_id: String,
+    event_types: Vec<String>,
+    i_cal_uid: Option<String>,
     max_results: Option<usize>,
     order_by: Option<String>,
     page_token: Option<String>,
+    private_extended_property: Vec<String>,
+    q: Option<String>,
     show_deleted: Option<bool>,
     single_events: Option<bool>,
+    sync_token: Option<String>,
     time_max: Option<String>,
     time_min: Option<String>,
+    time_zone: Option<String>,
+    updated_min: Option<String>,
 }
 
 impl EventsListRequest {
     pub fn new(calendar_id: String) -> Self {
         Self {
             calendar_id,
+            event_types: Vec::new(),
+            i_cal_uid: None,
             max_results: None,
             order_by: None,
             page_token: None,
+            private_extended_property: Vec::new(),
+            q: None,
             show_deleted: None,
             single_events: None,
+            sync_token: None,
             time_max: None,
             time_min: None,
+            time_zone: None,
+            updated_min: None,
         }
     }
 
     impl_builder!(
+        event_types: Vec<String>,
+        i_cal_uid: Option<String>,
         max_results: Option<usize>,
         order_by: Option<String>,
         page_token: Option<String>,
+        q: Option<String>,
         show_deleted: Option<bool>,
         single_events: Option<bool>,
+        sync_token: Option<String>,
+        time_zone: Option<String>,
     );
 
+    /// Only return events with a private extended property matching
+    /// `name=value`. Can be given several times, in which case all have to
+    /// match.
+    #[allow(dead_code)]
+    pub fn private_extended_property(mut self, name: &str, value: &str) -> Self {
+        self.private_extended_property
+            .push(format!("{}={}", name, value));
+        self
+    }
+
     pub fn time_max<T, Tz>(mut self, time: T) -> Self
     where
         T: Into<Option<DateTime<Tz>>>,
         Tz: TimeZone,
         Tz::Offset: fmt::Display,
     {
-        self.time_max = time
-            .into()
-            .map(|dt| dt.naive_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string());
+        self.time_max = time.into().map(format_time);
         self
     }
 
@@ -69,43 +96,58 @@ impl EventsListRequest {
         Tz: TimeZone,
         Tz::Offset: fmt::Display,
     {
-        self.time_min = time
-            .into()
-            .map(|dt| dt.naive_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string());
+        self.time_min = time.into().map(format_time);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn updated_min<T, Tz>(mut self, time: T) -> Self
+    where
+        T: Into<Option<DateTime<Tz>>>,
+        Tz: TimeZone,
+        Tz::Offset: fmt::Display,
+    {
+        self.updated_min = time.into().map(format_time);
         self
     }
 
     pub async fn request(
         self,
-        base_url: &str,
-        token: &AccessToken,
+        client: &CalendarClient,
     ) -> Result<EventsListResponse, RequestError> {
-        let url = self.to_url(base_url)?;
-        let request = request(token, &url, Body::empty()).await?;
+        let url = self.to_url(client.base_url())?;
+        let request = client.request(Method::GET, &url, Vec::new()).await?;
         let body = parse_json_body(request)
             .await
             .map_err(RequestError::ResponseError)?;
         Ok(body)
     }
 
+    /// Requests every page of the result, one at a time, by following
+    /// `nextPageToken`.
+    pub fn pages<'a>(
+        self,
+        client: &'a CalendarClient,
+    ) -> impl Stream<Item = Result<EventsListResponse, RequestError>> + 'a {
+        follow_pages(self, move |request| request.request(client))
+    }
+
+    /// Requests every page of the result and collects them into a single
+    /// response, see [`collect_pages`].
+    pub async fn request_all(
+        self,
+        client: &CalendarClient,
+    ) -> Result<EventsListResponse, RequestError> {
+        collect_pages(self.pages(client)).await
+    }
+
     pub fn to_url(&self, base: &str) -> Result<Url, url::ParseError> {
+        let mut url = events_url(base, &self.calendar_id, &[])?;
         let params = self.params();
-        let params = if params.is_empty() {
-            "".to_string()
-        } else {
-            format!(
-                "?{}",
-                params
-                    .iter()
-                    .map(|(key, val)| format!("{}={}", key, val))
-                    .collect::<Vec<_>>()
-                    .join("&")
-            )
-        };
-        Url::parse(&format!(
-            "{}calendars/{}/events{}",
-            base, self.calendar_id, params
-        ))
+        if !params.is_empty() {
+            url.query_pairs_mut().extend_pairs(params);
+        }
+        Ok(url)
     }
 
     pub fn params(&self) -> Vec<(String, String)> {
@@ -119,47 +161,847 @@ impl EventsListRequest {
             };
         }
 
+        macro_rules! push_all {
+            ($vec:expr, $request:expr, $(($key:expr, $field:ident)),* $(,)?) => {
+                $(
+                    for value in &$request.$field {
+                        $vec.push(($key.to_string(), value.to_string()));
+                    }
+                )*
+            };
+        }
+
         let mut res = Vec::new();
         push_if_some!(
             res,
             self,
+            ("iCalUID", i_cal_uid),
+            ("maxResults", max_results),
+            ("orderBy", order_by),
             ("pageToken", page_token),
+            ("q", q),
             ("showDeleted", show_deleted),
             ("singleEvents", single_events),
+            ("syncToken", sync_token),
             ("timeMax", time_max),
             ("timeMin", time_min),
+            ("timeZone", time_zone),
+            ("updatedMin", updated_min),
+        );
+        push_all!(
+            res,
+            self,
+            ("eventTypes", event_types),
+            ("privateExtendedProperty", private_extended_property),
         );
         res
     }
 }
 
+/// Creates an event.
+#[derive(Debug, Clone)]
+pub struct EventsInsertRequest {
+    calendar_id: String,
+    event: EventBody,
+    send_updates: Option<String>,
+}
+
+impl EventsInsertRequest {
+    pub fn new(calendar_id: String, event: EventBody) -> Self {
+        Self {
+            calendar_id,
+            event,
+            send_updates: None,
+        }
+    }
+
+    impl_builder!(send_updates: Option<String>);
+
+    pub async fn request(self, client: &CalendarClient) -> Result<Event, RequestError> {
+        let mut url = events_url(client.base_url(), &self.calendar_id, &[])?;
+        if let Some(send_updates) = &self.send_updates {
+            url.query_pairs_mut()
+                .append_pair("sendUpdates", send_updates);
+        }
+        send_json(client, Method::POST, &url, &self.event).await
+    }
+}
+
+/// Updates the fields of an event that are set in the body and leaves the rest
+/// as they are.
+#[derive(Debug, Clone)]
+pub struct EventsPatchRequest {
+    calendar_id: String,
+    event_id: String,
+    event: EventBody,
+    send_updates: Option<String>,
+}
+
+impl EventsPatchRequest {
+    pub fn new(calendar_id: String, event_id: String, event: EventBody) -> Self {
+        Self {
+            calendar_id,
+            event_id,
+            event,
+            send_updates: None,
+        }
+    }
+
+    impl_builder!(send_updates: Option<String>);
+
+    pub async fn request(self, client: &CalendarClient) -> Result<Event, RequestError> {
+        let mut url = events_url(client.base_url(), &self.calendar_id, &[&self.event_id])?;
+        if let Some(send_updates) = &self.send_updates {
+            url.query_pairs_mut()
+                .append_pair("sendUpdates", send_updates);
+        }
+        send_json(client, Method::PATCH, &url, &self.event).await
+    }
+}
+
+/// Deletes an event. Deleting an instance of a recurring event cancels only
+/// that instance.
+#[derive(Debug, Clone)]
+pub struct EventsDeleteRequest {
+    calendar_id: String,
+    event_id: String,
+    send_updates: Option<String>,
+}
+
+impl EventsDeleteRequest {
+    pub fn new(calendar_id: String, event_id: String) -> Self {
+        Self {
+            calendar_id,
+            event_id,
+            send_updates: None,
+        }
+    }
+
+    impl_builder!(send_updates: Option<String>);
+
+    pub async fn request(self, client: &CalendarClient) -> Result<(), RequestError> {
+        let mut url = events_url(client.base_url(), &self.calendar_id, &[&self.event_id])?;
+        if let Some(send_updates) = &self.send_updates {
+            url.query_pairs_mut()
+                .append_pair("sendUpdates", send_updates);
+        }
+        let body = client.request(Method::DELETE, &url, Vec::new()).await?;
+        drain_body(body).await.map_err(RequestError::ResponseError)
+    }
+}
+
+/// Starts sending notifications to `channel` whenever an event in the
+/// calendar changes.
+#[derive(Debug, Clone)]
+pub struct EventsWatchRequest {
+    calendar_id: String,
+    channel: Channel,
+}
+
+impl EventsWatchRequest {
+    pub fn new(calendar_id: String, channel: Channel) -> Self {
+        Self {
+            calendar_id,
+            channel,
+        }
+    }
+
+    pub async fn request(self, client: &CalendarClient) -> Result<Channel, RequestError> {
+        let url = events_url(client.base_url(), &self.calendar_id, &["watch"])?;
+        send_json(client, Method::POST, &url, &self.channel).await
+    }
+}
+
+/// Creates an event from a line of text, e.g. `Styrelsemöte tomorrow 17:15`.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct EventsQuickAddRequest {
+    calendar_id: String,
+    text: String,
+    send_updates: Option<String>,
+}
+
+#[allow(dead_code)]
+impl EventsQuickAddRequest {
+    pub fn new(calendar_id: String, text: String) -> Self {
+        Self {
+            calendar_id,
+            text,
+            send_updates: None,
+        }
+    }
+
+    impl_builder!(send_updates: Option<String>);
+
+    pub async fn request(self, client: &CalendarClient) -> Result<Event, RequestError> {
+        let mut url = events_url(client.base_url(), &self.calendar_id, &["quickAdd"])?;
+        url.query_pairs_mut().append_pair("text", &self.text);
+        if let Some(send_updates) = &self.send_updates {
+            url.query_pairs_mut()
+                .append_pair("sendUpdates", send_updates);
+        }
+        let body = client.request(Method::POST, &url, Vec::new()).await?;
+        parse_json_body(body)
+            .await
+            .map_err(RequestError::ResponseError)
+    }
+}
+
+/// Lists the instances of a recurring event.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct EventsInstancesRequest {
+    calendar_id: String,
+    event_id: String,
+    max_results: Option<usize>,
+    original_start: Option<String>,
+    page_token: Option<String>,
+    show_deleted: Option<bool>,
+    time_max: Option<String>,
+    time_min: Option<String>,
+    time_zone: Option<String>,
+}
+
+#[allow(dead_code)]
+impl EventsInstancesRequest {
+    pub fn new(calendar_id: String, event_id: String) -> Self {
+        Self {
+            calendar_id,
+            event_id,
+            max_results: None,
+            original_start: None,
+            page_token: None,
+            show_deleted: None,
+            time_max: None,
+            time_min: None,
+            time_zone: None,
+        }
+    }
+
+    impl_builder!(
+        max_results: Option<usize>,
+        page_token: Option<String>,
+        show_deleted: Option<bool>,
+        time_zone: Option<String>,
+    );
+
+    pub fn original_start<T, Tz>(mut self, time: T) -> Self
+    where
+        T: Into<Option<DateTime<Tz>>>,
+        Tz: TimeZone,
+        Tz::Offset: fmt::Display,
+    {
+        self.original_start = time.into().map(|dt| dt.to_rfc3339());
+        self
+    }
+
+    pub fn time_max<T, Tz>(mut self, time: T) -> Self
+    where
+        T: Into<Option<DateTime<Tz>>>,
+        Tz: TimeZone,
+        Tz::Offset: fmt::Display,
+    {
+        self.time_max = time.into().map(format_time);
+        self
+    }
+
+    pub fn time_min<T, Tz>(mut self, time: T) -> Self
+    where
+        T: Into<Option<DateTime<Tz>>>,
+        Tz: TimeZone,
+        Tz::Offset: fmt::Display,
+    {
+        self.time_min = time.into().map(format_time);
+        self
+    }
+
+    pub async fn request(
+        self,
+        client: &CalendarClient,
+    ) -> Result<EventsListResponse, RequestError> {
+        let url = self.to_url(client.base_url())?;
+        let body = client.request(Method::GET, &url, Vec::new()).await?;
+        parse_json_body(body)
+            .await
+            .map_err(RequestError::ResponseError)
+    }
+
+    pub fn to_url(&self, base: &str) -> Result<Url, url::ParseError> {
+        let mut url = events_url(base, &self.calendar_id, &[&self.event_id, "instances"])?;
+        let params = self.params();
+        if !params.is_empty() {
+            url.query_pairs_mut().extend_pairs(params);
+        }
+        Ok(url)
+    }
+
+    pub fn params(&self) -> Vec<(String, String)> {
+        macro_rules! push_if_some {
+            ($vec:expr, $request:expr, $(($key:expr, $field:ident)),* $(,)?) => {
+                $(
+                    if let Some(value) = &$request.$field {
+                        $vec.push(($key.to_string(), value.to_string()));
+                    }
+                )*
+            };
+        }
+
+        let mut res = Vec::new();
+        push_if_some!(
+            res,
+            self,
+            ("maxResults", max_results),
+            ("originalStart", original_start),
+            ("pageToken", page_token),
+            ("showDeleted", show_deleted),
+            ("timeMax", time_max),
+            ("timeMin", time_min),
+            ("timeZone", time_zone),
+        );
+        res
+    }
+}
+
+/// The writable fields of an event, used when creating or patching events.
+/// Fields that are `None` are left out of the request, which for a patch means
+/// that they are left unchanged.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start: Option<GCalTimestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<GCalTimestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<EventStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extended_properties: Option<ExtendedProperties>,
+}
+
+impl EventBody {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    impl_builder!(
+        summary: Option<String>,
+        description: Option<String>,
+        location: Option<String>,
+        start: Option<GCalTimestamp>,
+        end: Option<GCalTimestamp>,
+        status: Option<EventStatus>,
+        color_id: Option<String>,
+        extended_properties: Option<ExtendedProperties>,
+    );
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EventsListResponse {
+    #[serde(default)]
     items: Vec<Event>,
     next_page_token: Option<String>,
+    next_sync_token: Option<String>,
 }
 
 impl EventsListResponse {
-    impl_get!(items: &[Event]);
+    impl_get!(
+        items: &[Event],
+        next_page_token: &Option<String>,
+        next_sync_token: &Option<String>,
+    );
+
+    pub fn into_items(self) -> Vec<Event> {
+        self.items
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Event {
+    id: String,
+    status: Option<EventStatus>,
+    // Cancelled events returned by an incremental sync only carry their id and
+    // status, so everything else needs a default.
+    #[serde(default)]
     start: GCalTimestamp,
+    #[serde(default)]
     end: GCalTimestamp,
     location: Option<String>,
+    #[serde(default)]
     summary: String,
+    description: Option<String>,
+    color_id: Option<String>,
     end_time_unspecified: Option<bool>,
+    html_link: Option<String>,
+    recurring_event_id: Option<String>,
+    original_start_time: Option<GCalTimestamp>,
+    #[serde(default)]
+    attendees: Vec<Attendee>,
+    organizer: Option<Person>,
+    updated: Option<String>,
+    conference_data: Option<ConferenceData>,
+    #[serde(default)]
+    extended_properties: ExtendedProperties,
+    reminders: Option<Reminders>,
 }
 
 impl Event {
     impl_get!(
+        id: &str,
+        status: &Option<EventStatus>,
         start: &GCalTimestamp,
         end: &GCalTimestamp,
         location: &Option<String>,
         summary: &str,
+        description: &Option<String>,
+        color_id: &Option<String>,
         end_time_unspecified: &Option<bool>,
+        html_link: &Option<String>,
+        recurring_event_id: &Option<String>,
+        original_start_time: &Option<GCalTimestamp>,
+        attendees: &[Attendee],
+        organizer: &Option<Person>,
+        conference_data: &Option<ConferenceData>,
+        extended_properties: &ExtendedProperties,
+        reminders: &Option<Reminders>,
+    );
+
+    /// An event that doesn't come from Google, e.g. one read from an ICS
+    /// feed.
+    pub fn new(id: String, summary: String, start: GCalTimestamp, end: GCalTimestamp) -> Self {
+        Self {
+            id,
+            status: None,
+            start,
+            end,
+            location: None,
+            summary,
+            description: None,
+            color_id: None,
+            end_time_unspecified: None,
+            html_link: None,
+            recurring_event_id: None,
+            original_start_time: None,
+            attendees: Vec::new(),
+            organizer: None,
+            updated: None,
+            conference_data: None,
+            extended_properties: ExtendedProperties::default(),
+            reminders: None,
+        }
+    }
+
+    pub fn with_location(mut self, location: Option<String>) -> Self {
+        self.location = location;
+        self
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+
+    pub fn with_recurring_event_id(mut self, recurring_event_id: Option<String>) -> Self {
+        self.recurring_event_id = recurring_event_id;
+        self
+    }
+
+    pub fn with_original_start_time(mut self, original_start_time: Option<GCalTimestamp>) -> Self {
+        self.original_start_time = original_start_time;
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.status == Some(EventStatus::Cancelled)
+    }
+
+    /// When the event was last modified.
+    #[allow(dead_code)]
+    pub fn updated(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(self.updated.as_ref()?).ok()
+    }
+
+    /// The link to the video call of the event, if there is one.
+    #[allow(dead_code)]
+    pub fn video_uri(&self) -> Option<&str> {
+        self.conference_data
+            .as_ref()?
+            .entry_points
+            .iter()
+            .find(|entry_point| entry_point.entry_point_type == "video")
+            .map(|entry_point| entry_point.uri.as_str())
+    }
+
+    #[allow(dead_code)]
+    pub fn private_property(&self, name: &str) -> Option<&str> {
+        self.extended_properties
+            .private
+            .get(name)
+            .map(String::as_str)
+    }
+
+    #[allow(dead_code)]
+    pub fn shared_property(&self, name: &str) -> Option<&str> {
+        self.extended_properties
+            .shared
+            .get(name)
+            .map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EventStatus {
+    Confirmed,
+    Tentative,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Person {
+    id: Option<String>,
+    email: Option<String>,
+    display_name: Option<String>,
+    #[serde(rename = "self", default)]
+    is_self: bool,
+}
+
+impl Person {
+    impl_get!(
+        id: &Option<String>,
+        email: &Option<String>,
+        display_name: &Option<String>,
+        is_self: &bool,
+    );
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attendee {
+    #[serde(flatten)]
+    person: Person,
+    #[serde(default)]
+    organizer: bool,
+    #[serde(default)]
+    resource: bool,
+    #[serde(default)]
+    optional: bool,
+    response_status: ResponseStatus,
+    comment: Option<String>,
+    #[serde(default)]
+    additional_guests: u32,
+}
+
+impl Attendee {
+    impl_get!(
+        person: &Person,
+        organizer: &bool,
+        resource: &bool,
+        optional: &bool,
+        response_status: &ResponseStatus,
+        comment: &Option<String>,
+        additional_guests: &u32,
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseStatus {
+    NeedsAction,
+    Declined,
+    Tentative,
+    Accepted,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConferenceData {
+    conference_id: Option<String>,
+    #[serde(default)]
+    entry_points: Vec<EntryPoint>,
+    conference_solution: Option<ConferenceSolution>,
+}
+
+impl ConferenceData {
+    impl_get!(
+        conference_id: &Option<String>,
+        entry_points: &[EntryPoint],
+        conference_solution: &Option<ConferenceSolution>,
     );
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryPoint {
+    /// One of `video`, `phone`, `sip` and `more`.
+    entry_point_type: String,
+    uri: String,
+    label: Option<String>,
+}
+
+impl EntryPoint {
+    impl_get!(
+        entry_point_type: &str,
+        uri: &str,
+        label: &Option<String>,
+    );
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConferenceSolution {
+    name: String,
+    icon_uri: Option<String>,
+}
+
+impl ConferenceSolution {
+    impl_get!(name: &str, icon_uri: &Option<String>);
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ExtendedProperties {
+    #[serde(default)]
+    private: HashMap<String, String>,
+    #[serde(default)]
+    shared: HashMap<String, String>,
+}
+
+impl ExtendedProperties {
+    impl_get!(
+        private: &HashMap<String, String>,
+        shared: &HashMap<String, String>,
+    );
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reminders {
+    use_default: bool,
+    #[serde(default)]
+    overrides: Vec<ReminderOverride>,
+}
+
+impl Reminders {
+    impl_get!(use_default: &bool, overrides: &[ReminderOverride]);
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReminderOverride {
+    /// Either `email` or `popup`.
+    method: String,
+    minutes: u32,
+}
+
+impl ReminderOverride {
+    impl_get!(method: &str, minutes: &u32);
+}
+
+/// Fetches the pages of `request` with `fetch`, one at a time, until one
+/// has no `nextPageToken`.
+fn follow_pages<F, Fut>(
+    request: EventsListRequest,
+    fetch: F,
+) -> impl Stream<Item = Result<EventsListResponse, RequestError>>
+where
+    F: Fn(EventsListRequest) -> Fut,
+    Fut: Future<Output = Result<EventsListResponse, RequestError>>,
+{
+    stream::try_unfold(Some(request), move |request| {
+        let page = request.clone().map(&fetch);
+        async move {
+            let (request, page) = match (request, page) {
+                (Some(request), Some(page)) => (request, page.await?),
+                _ => return Ok(None),
+            };
+            let next = page
+                .next_page_token
+                .clone()
+                .map(|page_token| request.page_token(page_token));
+            Ok(Some((page, next)))
+        }
+    })
+}
+
+/// Collects every page into a single response. The sync token is taken from
+/// the last page, which is the only one that has it.
+async fn collect_pages(
+    pages: impl Stream<Item = Result<EventsListResponse, RequestError>>,
+) -> Result<EventsListResponse, RequestError> {
+    pages
+        .try_fold(
+            EventsListResponse {
+                items: Vec::new(),
+                next_page_token: None,
+                next_sync_token: None,
+            },
+            |mut all, page| async move {
+                all.items.extend(page.items);
+                all.next_sync_token = page.next_sync_token;
+                Ok(all)
+            },
+        )
+        .await
+}
+
+/// The URL of the events collection of a calendar, with `segments` appended.
+/// The calendar id is percent-encoded since e.g. holiday calendars have a `#`
+/// in it.
+fn events_url(base: &str, calendar_id: &str, segments: &[&str]) -> Result<Url, url::ParseError> {
+    let mut url = Url::parse(base)?;
+    url.path_segments_mut()
+        .map_err(|_| url::ParseError::RelativeUrlWithCannotBeABaseBase)?
+        .pop_if_empty()
+        .extend(&["calendars", calendar_id, "events"])
+        .extend(segments);
+    Ok(url)
+}
+
+/// Sends `body` as JSON and parses the response.
+async fn send_json<B: Serialize, T: DeserializeOwned>(
+    client: &CalendarClient,
+    method: Method,
+    url: &Url,
+    body: &B,
+) -> Result<T, RequestError> {
+    let body = serde_json::to_vec(body)
+        .map_err(|e| RequestError::ResponseError(BodyParseError::JsonError(e)))?;
+    let response = client.request(method, url, body).await?;
+    parse_json_body(response)
+        .await
+        .map_err(RequestError::ResponseError)
+}
+
+/// Formats a time the way the API wants it, in UTC.
+fn format_time<Tz: TimeZone>(time: DateTime<Tz>) -> String {
+    time.with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://www.googleapis.com/calendar/v3/";
+
+    /// A page with one event named after `token`, pointing at `next`.
+    fn page(token: &str, next: Option<&str>, sync_token: Option<&str>) -> EventsListResponse {
+        let day = chrono::NaiveDate::from_ymd(2030, 3, 14);
+        EventsListResponse {
+            items: vec![Event::new(
+                token.to_string(),
+                token.to_string(),
+                GCalTimestamp::from_date(day),
+                GCalTimestamp::from_date(day.succ()),
+            )],
+            next_page_token: next.map(str::to_string),
+            next_sync_token: sync_token.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn following_pages() {
+        let requested = std::sync::Mutex::new(Vec::new());
+        let pages = follow_pages(
+            EventsListRequest::new("primary".to_string()).single_events(true),
+            |request| {
+                requested.lock().unwrap().push(request.params());
+                let page = match request.page_token.as_deref() {
+                    None => page("first", Some("2"), None),
+                    Some("2") => page("second", Some("3"), None),
+                    Some("3") => page("third", None, Some("sync")),
+                    Some(token) => panic!("unexpected page token {}", token),
+                };
+                async move { Ok(page) }
+            },
+        );
+        let all = collect_pages(pages).await.unwrap();
+        let ids: Vec<_> = all.items().iter().map(Event::id).collect();
+        assert_eq!(ids, ["first", "second", "third"]);
+        assert_eq!(all.next_page_token(), &None);
+        assert_eq!(all.next_sync_token().as_deref(), Some("sync"));
+
+        // Every page is asked for with the same parameters.
+        let param = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let single_events = param("singleEvents", "true");
+        assert_eq!(
+            requested.into_inner().unwrap(),
+            [
+                vec![single_events.clone()],
+                vec![param("pageToken", "2"), single_events.clone()],
+                vec![param("pageToken", "3"), single_events],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_page() {
+        let pages = follow_pages(EventsListRequest::new("primary".to_string()), |request| {
+            let page = match request.page_token {
+                None => Ok(page("first", Some("2"), Some("stale"))),
+                Some(_) => Err(RequestError::Timeout),
+            };
+            async move { page }
+        });
+        assert!(matches!(
+            collect_pages(pages).await,
+            Err(RequestError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn list_url() {
+        let url =
+            EventsListRequest::new("sv.swedish#holiday@group.v.calendar.google.com".to_string())
+                .event_types(vec!["default".to_string(), "focusTime".to_string()])
+                .max_results(250)
+                .order_by("startTime".to_string())
+                .q("styrelse möte&fika".to_string())
+                .single_events(true)
+                .time_min(Utc.ymd(2030, 3, 14).and_hms(16, 15, 0))
+                .time_zone("Europe/Stockholm".to_string())
+                .private_extended_property("kodapa", "a=b")
+                .private_extended_property("meeting", "board")
+                .to_url(BASE)
+                .unwrap();
+        assert_eq!(
+            url.path(),
+            "/calendar/v3/calendars/sv.swedish%23holiday@group.v.calendar.google.com/events"
+        );
+        assert_eq!(
+            url.query(),
+            Some(concat!(
+                "maxResults=250",
+                "&orderBy=startTime",
+                "&q=styrelse+m%C3%B6te%26fika",
+                "&singleEvents=true",
+                "&timeMin=2030-03-14T16%3A15%3A00Z",
+                "&timeZone=Europe%2FStockholm",
+                "&eventTypes=default",
+                "&eventTypes=focusTime",
+                "&privateExtendedProperty=kodapa%3Da%3Db",
+                "&privateExtendedProperty=meeting%3Dboard",
+            ))
+        );
+    }
+
+    #[test]
+    fn list_url_without_params() {
+        let url = EventsListRequest::new("primary".to_string())
+            .to_url(BASE)
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://www.googleapis.com/calendar/v3/calendars/primary/events"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Push notifications from Google Calendar, so that changes are picked up
+//! right away instead of by polling every minute.
+//!
+//! See `https://developers.google.com/calendar/api/guides/push`.
+//!
+//! A channel is registered with `Events.watch`, telling Google to post to the
+//! configured address whenever an event changes. The notifications carry no
+//! data, they only tell us that an incremental sync is needed. Channels expire,
+//! so a new one is registered a while before that and the old one is stopped.
+//! The current channel is also stopped when the bot is shut down.
+//!
+//! To try the receiver without Google, post the headers it looks at, e.g.
+//!
+//! ```sh
+//! curl -X POST http://localhost:8080/ \
+//!     -H "X-Goog-Channel-Token: $WEBHOOK_TOKEN" \
+//!     -H "X-Goog-Resource-State: exists"
+//! ```
+
+use chrono::{Duration, Utc};
+use hyper::{server::conn::Http, service::service_fn, Body, Request, Response, StatusCode};
+use rand::{distributions::Alphanumeric, Rng};
+use std::{convert::Infallible, future::Future, io};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_native_tls::{native_tls, TlsAcceptor};
+
+use crate::{
+    calendar::{
+        model::{
+            channels::{Channel, ChannelsStopRequest},
+            events::EventsWatchRequest,
+        },
+        CalendarClient,
+    },
+    config::WebhookConfig,
+};
+
+/// How long before a channel expires to replace it, or half its lifetime if
+/// that is shorter.
+const RENEW_MARGIN: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// How long to wait before trying to register a channel again after failing.
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The receiving end of the notifications, bound to its address.
+pub struct Webhook {
+    config: WebhookConfig,
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    /// Sent along with every notification so that we know it's from Google.
+    token: String,
+}
+
+impl Webhook {
+    pub async fn bind(config: WebhookConfig) -> io::Result<Self> {
+        let acceptor = match &config.tls_identity {
+            Some(path) => {
+                let password = std::env::var("WEBHOOK_TLS_PASSWORD").unwrap_or_default();
+                let identity = native_tls::Identity::from_pkcs12(&std::fs::read(path)?, &password)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let acceptor = native_tls::TlsAcceptor::new(identity).map_err(io::Error::other)?;
+                Some(TlsAcceptor::from(acceptor))
+            }
+            None => None,
+        };
+        Ok(Self {
+            listener: TcpListener::bind(config.listen).await?,
+            acceptor,
+            token: std::env::var("WEBHOOK_TOKEN").unwrap_or_else(|_| random_string()),
+            config,
+        })
+    }
+
+    /// Keeps a channel registered and sends a message on `sender` for every
+    /// notification. Returns once the bot is shutting down and the channel
+    /// has been stopped.
+    pub async fn run(self, client: &CalendarClient, sender: mpsc::UnboundedSender<()>) {
+        let Self {
+            config,
+            listener,
+            acceptor,
+            token,
+        } = self;
+        tokio::select! {
+            _ = serve(listener, acceptor, token.clone(), sender.clone()) => (),
+            _ = watch(client, &config, &token, sender) => (),
+        }
+    }
+}
+
+/// Accepts connections forever.
+async fn serve(
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    token: String,
+    sender: mpsc::UnboundedSender<()>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                println!("webhook: unable to accept connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let token = token.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let response = handle_notification(&request, &token, &sender);
+                async move { Ok::<_, Infallible>(response) }
+            });
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => Http::new().serve_connection(stream, service).await,
+                    Err(e) => {
+                        println!("webhook: tls error: {}", e);
+                        return;
+                    }
+                },
+                None => Http::new().serve_connection(stream, service).await,
+            };
+            if let Err(e) = result {
+                println!("webhook: connection error: {}", e);
+            }
+        });
+    }
+}
+
+fn handle_notification(
+    request: &Request<Body>,
+    token: &str,
+    sender: &mpsc::UnboundedSender<()>,
+) -> Response<Body> {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let status = if header("X-Goog-Channel-Token") != Some(token) {
+        StatusCode::FORBIDDEN
+    } else {
+        match header("X-Goog-Resource-State") {
+            // `sync` is sent when a channel is created.
+            Some(state @ ("sync" | "exists" | "not_exists")) => {
+                println!("webhook: notification ({})", state);
+                let _ = sender.send(());
+                StatusCode::OK
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    };
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Registers channels until the bot is shut down, and then stops the current
+/// one.
+async fn watch(
+    client: &CalendarClient,
+    config: &WebhookConfig,
+    token: &str,
+    sender: mpsc::UnboundedSender<()>,
+) {
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut current: Option<Channel> = None;
+    loop {
+        let channel = Channel::web_hook(
+            format!("kodapa-{}", random_string()),
+            config.address.clone(),
+            token.to_string(),
+            config.ttl,
+        );
+        let renew_at = match EventsWatchRequest::new(client.calendar_id().to_string(), channel)
+            .request(client)
+            .await
+        {
+            Ok(channel) => {
+                println!("webhook: watching with channel {}", channel.id());
+                let expiration = channel
+                    .expiration()
+                    .unwrap_or_else(|| Utc::now() + Duration::seconds(config.ttl as i64));
+                let now = Utc::now();
+                let margin = Duration::from_std(RENEW_MARGIN)
+                    .unwrap()
+                    .min((expiration - now) / 2);
+                // Never faster than retrying, even if the channel has already
+                // expired.
+                let renew_at =
+                    (expiration - margin).max(now + Duration::from_std(RETRY_DELAY).unwrap());
+                if let Some(old) = current.replace(channel) {
+                    stop(client, &old).await;
+                }
+                renew_at
+            }
+            Err(e) => {
+                println!("webhook: unable to watch calendar: {}", e);
+                Utc::now() + Duration::from_std(RETRY_DELAY).unwrap()
+            }
+        };
+        // Changes made while we didn't have a channel aren't notified.
+        let _ = sender.send(());
+
+        tokio::select! {
+            _ = tokio::time::sleep((renew_at - Utc::now()).to_std().unwrap_or_default()) => (),
+            _ = &mut shutdown => {
+                if let Some(channel) = current {
+                    stop(client, &channel).await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+async fn stop(client: &CalendarClient, channel: &Channel) {
+    match ChannelsStopRequest::new(channel).request(client).await {
+        Ok(()) => println!("webhook: stopped channel {}", channel.id()),
+        Err(e) => println!("webhook: unable to stop channel {}: {}", channel.id(), e),
+    }
+}
+
+/// Waits for Ctrl-C, or SIGTERM on Unix. The signals are listened for as soon
+/// as this is called, so one that arrives while a channel is being renewed
+/// isn't lost.
+fn shutdown_signal() -> impl Future<Output = ()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut interrupt = signal(SignalKind::interrupt()).expect("unable to listen for SIGINT");
+        let mut terminate = signal(SignalKind::terminate()).expect("unable to listen for SIGTERM");
+        async move {
+            tokio::select! {
+                _ = interrupt.recv() => (),
+                _ = terminate.recv() => (),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        async {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+fn random_string() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notify(token: Option<&str>, state: Option<&str>) -> (StatusCode, bool) {
+        let mut request = Request::post("/");
+        if let Some(token) = token {
+            request = request.header("X-Goog-Channel-Token", token);
+        }
+        if let Some(state) = state {
+            request = request.header("X-Goog-Resource-State", state);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let response = handle_notification(&request, "secret", &sender);
+        (response.status(), receiver.try_recv().is_ok())
+    }
+
+    #[test]
+    fn wrong_token() {
+        assert_eq!(notify(None, Some("exists")), (StatusCode::FORBIDDEN, false));
+        assert_eq!(
+            notify(Some("guess"), Some("exists")),
+            (StatusCode::FORBIDDEN, false)
+        );
+    }
+
+    #[test]
+    fn changes() {
+        for state in &["sync", "exists", "not_exists"] {
+            assert_eq!(
+                notify(Some("secret"), Some(state)),
+                (StatusCode::OK, true),
+                "{}",
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_state() {
+        assert_eq!(
+            notify(Some("secret"), Some("changed")),
+            (StatusCode::BAD_REQUEST, false)
+        );
+        assert_eq!(
+            notify(Some("secret"), None),
+            (StatusCode::BAD_REQUEST, false)
+        );
+    }
+}
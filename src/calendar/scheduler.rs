@@ -0,0 +1,388 @@
+//! Keeps track of when the next reminder should be sent.
+//!
+//! Which reminders have been sent is persisted, to `sent_reminders.json` by
+//! default, so that restarting the bot doesn't send them again. A reminder
+//! only counts as sent once Discord has it, see [`Delivery`].
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    calendar::model::events::Event,
+    config::{MeetingMatcher, Reminder},
+};
+
+pub const SENT_REMINDERS_FILE: &str = "sent_reminders.json";
+/// How long to wait before trying again when a reminder couldn't be sent.
+const RETRY_DELAY_MINUTES: i64 = 1;
+/// How long to wait for a reminder to be reported on before assuming it was
+/// lost on the way.
+const DELIVERY_TIMEOUT_MINUTES: i64 = 10;
+
+/// Identifies a single reminder. Keyed on the event and its start so that a
+/// meeting that is moved gets new reminders, and on the reminder offset so
+/// that every configured reminder is sent once.
+///
+/// Since events are listed with `singleEvents`, every instance of a recurring
+/// event has its own id and start.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct ReminderKey {
+    pub event_id: String,
+    pub start: DateTime<Utc>,
+    pub offset: i64,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Deadline {
+    at: DateTime<Utc>,
+    key: ReminderKey,
+    /// Index of the matcher and of the reminder within it.
+    reminder: (usize, usize),
+}
+
+/// Sent along with a due reminder so that whoever sends it can tell the
+/// scheduler how it went.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    key: ReminderKey,
+    sender: mpsc::UnboundedSender<(ReminderKey, bool)>,
+}
+
+impl Delivery {
+    pub fn report(self, delivered: bool) {
+        let _ = self.sender.send((self.key, delivered));
+    }
+}
+
+pub struct Scheduler {
+    meetings: Vec<MeetingMatcher>,
+    queue: BinaryHeap<Reverse<Deadline>>,
+    fired: HashSet<ReminderKey>,
+    /// Where `fired` is persisted.
+    file: PathBuf,
+    /// Reminders that have been handed out but not reported on yet. They are
+    /// not persisted, so if the bot dies before they are sent they are sent
+    /// after the restart instead.
+    in_flight: HashMap<ReminderKey, ((usize, usize), DateTime<Utc>)>,
+    reports: mpsc::UnboundedSender<(ReminderKey, bool)>,
+}
+
+impl Scheduler {
+    /// Reminders that have been sent are read from and written to `file`.
+    /// Deliveries of the reminders returned by [`Scheduler::pop_due`] are
+    /// reported on `reports` and should be passed to [`Scheduler::report`].
+    pub fn new(
+        meetings: Vec<MeetingMatcher>,
+        file: impl Into<PathBuf>,
+        reports: mpsc::UnboundedSender<(ReminderKey, bool)>,
+    ) -> Self {
+        let file = file.into();
+        Self {
+            meetings,
+            queue: BinaryHeap::new(),
+            fired: read_sent_reminders(&file),
+            file,
+            in_flight: HashMap::new(),
+            reports,
+        }
+    }
+
+    /// Throws away the current queue and builds a new one from `events`.
+    /// Should be called whenever the calendar changes.
+    ///
+    /// If several reminders for a meeting are already overdue, only the one
+    /// closest to the meeting is kept. There's no point in telling people to
+    /// add their points tomorrow if the meeting starts in 30 minutes.
+    pub fn rebuild<'a>(&mut self, events: impl IntoIterator<Item = &'a Event>) {
+        let now = Utc::now();
+        self.queue.clear();
+        let sent = self.fired.len();
+        self.fired.retain(|key| key.start > now);
+        let mut dirty = sent != self.fired.len();
+
+        for event in events {
+            let (m, matcher, start) = match self
+                .meetings
+                .iter()
+                .enumerate()
+                .find_map(|(m, matcher)| Some((m, matcher, matcher.start_of(event)?)))
+            {
+                Some((m, matcher, start)) if start > now => (m, matcher, start),
+                _ => continue,
+            };
+            let mut deadlines = matcher
+                .reminders
+                .iter()
+                .enumerate()
+                .map(|(r, reminder)| Deadline {
+                    at: start - Duration::minutes(reminder.offset),
+                    key: ReminderKey {
+                        event_id: event.id().to_string(),
+                        start,
+                        offset: reminder.offset,
+                    },
+                    reminder: (m, r),
+                })
+                .collect::<Vec<_>>();
+            deadlines.sort();
+
+            let overdue = deadlines.iter().filter(|d| d.at <= now).count();
+            for (i, deadline) in deadlines.into_iter().enumerate() {
+                if self.fired.contains(&deadline.key) || self.in_flight.contains_key(&deadline.key)
+                {
+                    continue;
+                }
+                if i + 1 < overdue {
+                    // Superseded by a later reminder that is also overdue.
+                    self.fired.insert(deadline.key);
+                    dirty = true;
+                    continue;
+                }
+                self.queue.push(Reverse(deadline));
+            }
+        }
+
+        if dirty {
+            write_sent_reminders(&self.file, &self.fired);
+        }
+    }
+
+    /// When the next reminder is due, if any.
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        self.queue.peek().map(|Reverse(deadline)| deadline.at)
+    }
+
+    /// Removes and returns all reminders that are due at `now`. Every reminder
+    /// is only returned once, even across restarts, unless its delivery is
+    /// reported as failed.
+    pub fn pop_due(&mut self, now: DateTime<Utc>) -> Vec<(ReminderKey, &Reminder, Delivery)> {
+        let timeout = Duration::minutes(DELIVERY_TIMEOUT_MINUTES);
+        let lost = self
+            .in_flight
+            .iter()
+            .filter(|(_, (_, since))| now - *since > timeout)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in lost {
+            self.report(key, false, now);
+        }
+
+        let mut due = Vec::new();
+        while let Some(Reverse(deadline)) = self.queue.peek() {
+            if deadline.at > now {
+                break;
+            }
+            let Reverse(Deadline { key, reminder, .. }) = self.queue.pop().unwrap();
+            if self.fired.contains(&key) || self.in_flight.contains_key(&key) {
+                continue;
+            }
+            self.in_flight.insert(key.clone(), (reminder, now));
+            let delivery = Delivery {
+                key: key.clone(),
+                sender: self.reports.clone(),
+            };
+            due.push((
+                key,
+                &self.meetings[reminder.0].reminders[reminder.1],
+                delivery,
+            ));
+        }
+        due
+    }
+
+    /// Marks a reminder as sent, or queues it again a bit after `now` if it
+    /// couldn't be sent.
+    pub fn report(&mut self, key: ReminderKey, delivered: bool, now: DateTime<Utc>) {
+        let reminder = match self.in_flight.remove(&key) {
+            Some((reminder, _)) => reminder,
+            None => return,
+        };
+        if delivered {
+            self.fired.insert(key);
+            write_sent_reminders(&self.file, &self.fired);
+        } else if key.start > now {
+            self.queue.push(Reverse(Deadline {
+                at: now + Duration::minutes(RETRY_DELAY_MINUTES),
+                key,
+                reminder,
+            }));
+        }
+    }
+}
+
+fn read_sent_reminders(file: &Path) -> HashSet<ReminderKey> {
+    match fs::read_to_string(file) {
+        Ok(s) => serde_json::from_str(&s)
+            .unwrap_or_else(|e| panic!("Error parsing {}: {}", file.display(), e)),
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// Writes to a temporary file first so that a crash mid-write can't leave us
+/// with a corrupt file.
+fn write_sent_reminders(file: &Path, sent: &HashSet<ReminderKey>) {
+    let mut sent = sent.iter().collect::<Vec<_>>();
+    sent.sort();
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let fail = |e| panic!("Can't write {}: {}", file.display(), e);
+    fs::write(
+        &tmp,
+        serde_json::to_string_pretty(&sent).expect("Can't serialize sent reminders"),
+    )
+    .unwrap_or_else(fail);
+    fs::rename(&tmp, file).unwrap_or_else(fail);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calendar::model::GCalTimestamp, config::Config};
+
+    /// A scheduler for board meetings with reminders at `offsets`, persisting
+    /// to a file of its own.
+    fn scheduler(name: &str, offsets: &[i64]) -> (Scheduler, PathBuf) {
+        let file = std::env::temp_dir().join(format!(
+            "kodapa-sent-reminders-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&file);
+        let mut meetings = Config::default().meetings;
+        meetings[0].reminders = offsets
+            .iter()
+            .map(|&offset| Reminder {
+                offset,
+                message: String::new(),
+                channel: None,
+            })
+            .collect();
+        let (reports, _) = mpsc::unbounded_channel();
+        (Scheduler::new(meetings, &file, reports), file)
+    }
+
+    fn meeting(start: DateTime<Utc>) -> Event {
+        let start = start.with_timezone(&chrono_tz::UTC);
+        Event::new(
+            "meeting".to_string(),
+            "Styrelsemöte".to_string(),
+            GCalTimestamp::from_date_time(start),
+            GCalTimestamp::from_date_time(start + Duration::hours(1)),
+        )
+    }
+
+    /// The offsets of the reminders due at `at`, in order.
+    fn due(scheduler: &mut Scheduler, at: DateTime<Utc>) -> Vec<(ReminderKey, i64)> {
+        scheduler
+            .pop_due(at)
+            .into_iter()
+            .map(|(key, reminder, _)| (key, reminder.offset))
+            .collect()
+    }
+
+    #[test]
+    fn every_reminder_once() {
+        let (mut scheduler, file) = scheduler("once", &[24 * 60, 60]);
+        let now = Utc::now();
+        let events = [meeting(now + Duration::hours(2))];
+        scheduler.rebuild(&events);
+
+        // The day-before reminder is overdue, so it's sent right away.
+        let sent = due(&mut scheduler, now);
+        assert_eq!(sent.iter().map(|(_, o)| *o).collect::<Vec<_>>(), [24 * 60]);
+        assert!(due(&mut scheduler, now).is_empty());
+        scheduler.report(sent[0].0.clone(), true, now);
+        scheduler.rebuild(&events);
+        assert!(due(&mut scheduler, now).is_empty());
+
+        let later = now + Duration::minutes(61);
+        let sent = due(&mut scheduler, later);
+        assert_eq!(sent.iter().map(|(_, o)| *o).collect::<Vec<_>>(), [60]);
+        scheduler.report(sent[0].0.clone(), true, now);
+        assert!(due(&mut scheduler, later).is_empty());
+
+        // Nor after a restart.
+        let (reports, _) = mpsc::unbounded_channel();
+        let mut restarted = Scheduler::new(scheduler.meetings.clone(), &file, reports);
+        restarted.rebuild(&events);
+        assert_eq!(restarted.next_deadline(), None);
+        assert!(due(&mut restarted, later).is_empty());
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn overdue_reminders_are_superseded() {
+        let (mut scheduler, file) = scheduler("superseded", &[24 * 60, 60, 10]);
+        let now = Utc::now();
+        scheduler.rebuild(&[meeting(now + Duration::minutes(30))]);
+        let sent = due(&mut scheduler, now);
+        assert_eq!(sent.iter().map(|(_, o)| *o).collect::<Vec<_>>(), [60]);
+        scheduler.report(sent[0].0.clone(), true, now);
+        // The day-before reminder counts as sent, so it isn't sent after a
+        // restart either.
+        let sent = read_sent_reminders(&file);
+        assert_eq!(
+            sent.iter().map(|key| key.offset).collect::<HashSet<_>>(),
+            HashSet::from([24 * 60, 60])
+        );
+        let sent = due(&mut scheduler, now + Duration::minutes(21));
+        assert_eq!(sent.iter().map(|(_, o)| *o).collect::<Vec<_>>(), [10]);
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn failed_deliveries_are_retried() {
+        let (mut scheduler, file) = scheduler("retry", &[60]);
+        let now = Utc::now();
+        scheduler.rebuild(&[meeting(now + Duration::minutes(30))]);
+        let sent = due(&mut scheduler, now);
+        assert_eq!(sent.len(), 1);
+        scheduler.report(sent[0].0.clone(), false, now);
+        let retry = now + Duration::minutes(RETRY_DELAY_MINUTES);
+        assert!(due(&mut scheduler, retry - Duration::seconds(1)).is_empty());
+        let sent = due(&mut scheduler, retry);
+        assert_eq!(sent.len(), 1);
+        scheduler.report(sent[0].0.clone(), true, retry);
+        assert!(due(&mut scheduler, retry).is_empty());
+        assert!(read_sent_reminders(&file).contains(&sent[0].0));
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn failed_deliveries_after_the_start_are_dropped() {
+        let (mut scheduler, file) = scheduler("late", &[60]);
+        let now = Utc::now();
+        let start = now + Duration::minutes(30);
+        scheduler.rebuild(&[meeting(start)]);
+        let sent = due(&mut scheduler, now);
+        assert_eq!(sent.len(), 1);
+        scheduler.report(sent[0].0.clone(), false, start);
+        assert!(due(&mut scheduler, start + Duration::hours(1)).is_empty());
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn lost_deliveries_time_out() {
+        let (mut scheduler, file) = scheduler("timeout", &[60]);
+        let now = Utc::now();
+        scheduler.rebuild(&[meeting(now + Duration::minutes(50))]);
+        assert_eq!(due(&mut scheduler, now).len(), 1);
+        // Not reported, but still in flight.
+        assert!(due(&mut scheduler, now + Duration::minutes(5)).is_empty());
+        scheduler.rebuild(&[meeting(now + Duration::minutes(50))]);
+        assert!(due(&mut scheduler, now + Duration::minutes(5)).is_empty());
+        // Lost deliveries are retried like failed ones.
+        let timeout = now + Duration::minutes(DELIVERY_TIMEOUT_MINUTES + 1);
+        assert!(due(&mut scheduler, timeout).is_empty());
+        let retry = timeout + Duration::minutes(RETRY_DELAY_MINUTES);
+        assert_eq!(due(&mut scheduler, retry).len(), 1);
+        let _ = fs::remove_file(&file);
+    }
+}
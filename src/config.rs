@@ -0,0 +1,439 @@
+//! Bot configuration, read from `config.json`.
+//!
+//! Secrets and ids are still given as environment variables. This file is for
+//! things that are more structured than what fits in a variable. Every field
+//! has a default, so the file can be left out completely.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, convert::TryFrom, fs, net::SocketAddr, sync::OnceLock};
+
+use crate::calendar::model::events::Event;
+
+static TIME_ZONE: OnceLock<Tz> = OnceLock::new();
+
+/// The configured time zone, which times are shown and entered in.
+pub fn time_zone() -> Tz {
+    *TIME_ZONE.get_or_init(|| default_time_zone().0)
+}
+
+/// The current time in the configured time zone.
+pub fn now() -> DateTime<Tz> {
+    Utc::now().with_timezone(&time_zone())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Config {
+    /// The IANA time zone that times are shown and entered in, and that
+    /// events without a time zone of their own are in.
+    #[serde(default = "default_time_zone")]
+    pub time_zone: TimeZoneName,
+    /// Which events are meetings and what reminders to send for them.
+    #[serde(default = "default_meetings")]
+    pub meetings: Vec<MeetingMatcher>,
+    /// How to authenticate against Google Calendar.
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Where the events come from.
+    #[serde(default)]
+    pub source: SourceConfig,
+    /// How to talk to Google Calendar.
+    #[serde(default)]
+    pub calendar: CalendarConfig,
+    /// Roles that members can give themselves with `/subscribe`.
+    #[serde(default)]
+    pub roles: Vec<SubscriptionRole>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            time_zone: default_time_zone(),
+            meetings: default_meetings(),
+            auth: AuthConfig::default(),
+            source: SourceConfig::default(),
+            calendar: CalendarConfig::default(),
+            roles: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config and sets the time zone returned by [`time_zone`].
+    pub fn read() -> Self {
+        let config: Self = match fs::read_to_string("config.json") {
+            Ok(s) => serde_json::from_str(&s).expect("Error parsing config.json"),
+            Err(_) => Self::default(),
+        };
+        let _ = TIME_ZONE.set(config.time_zone.0);
+        config
+    }
+}
+
+/// An IANA time zone that can be read from the config, e.g.
+/// `Europe/Stockholm`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeZoneName(pub Tz);
+
+impl TryFrom<String> for TimeZoneName {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Ok(Self(s.parse()?))
+    }
+}
+
+impl From<TimeZoneName> for String {
+    fn from(time_zone: TimeZoneName) -> Self {
+        time_zone.0.name().to_string()
+    }
+}
+
+fn default_time_zone() -> TimeZoneName {
+    TimeZoneName(Tz::Europe__Stockholm)
+}
+
+/// A notification role that members can opt in to, e.g.
+///
+/// ```json
+/// { "name": "Hack nights", "role": 123456789012345678 }
+/// ```
+///
+/// The bot's own role has to be above it for the bot to hand it out.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubscriptionRole {
+    /// What members pick in `/subscribe`.
+    pub name: String,
+    /// The id of the Discord role.
+    pub role: u64,
+}
+
+/// Where the events come from, e.g.
+///
+/// ```json
+/// { "type": "ics", "location": "https://example.com/calendar.ics" }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceConfig {
+    #[default]
+    /// The calendar in `CALENDAR_ID`. This is the only source where meetings
+    /// can be scheduled with `/meeting`.
+    Google,
+    /// An iCalendar file, given as a path or an `http(s)://` or `webcal://`
+    /// URL. The proxy, timeout and user agent in `calendar` are used for URLs.
+    Ics { location: String },
+    /// A calendar collection on a CalDAV server. The username and password
+    /// are read from `CALDAV_USERNAME` and `CALDAV_PASSWORD`.
+    #[serde(rename = "caldav")]
+    CalDav { url: String },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CalendarConfig {
+    /// The root of the API, ending with a `/`.
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+    /// An HTTP proxy to send all requests through, e.g.
+    /// `http://proxy.example.com:3128`.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Seconds to wait for a response before giving up.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// Get push notifications from Google instead of polling every minute.
+    #[serde(default)]
+    pub webhook: Option<WebhookConfig>,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+            proxy: None,
+            timeout: default_timeout(),
+            user_agent: default_user_agent(),
+            webhook: None,
+        }
+    }
+}
+
+fn default_base_url() -> String {
+    "https://www.googleapis.com/calendar/v3/".to_string()
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+fn default_user_agent() -> String {
+    format!("kodapa/{}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Where to receive push notifications from Google Calendar, e.g.
+///
+/// ```json
+/// { "listen": "0.0.0.0:8080", "address": "https://kodapa.example.com/calendar" }
+/// ```
+///
+/// The token Google sends along is read from `WEBHOOK_TOKEN`, or made up at
+/// startup if it isn't set.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebhookConfig {
+    /// The address to listen on.
+    pub listen: SocketAddr,
+    /// The public HTTPS URL that Google posts to. It has to reach `listen`.
+    pub address: String,
+    /// A PKCS #12 file with the certificate and key to serve HTTPS with. The
+    /// password is read from `WEBHOOK_TLS_PASSWORD`. Without it, plain HTTP is
+    /// served, e.g. behind a reverse proxy.
+    #[serde(default)]
+    pub tls_identity: Option<String>,
+    /// How many seconds a channel should live before it is renewed.
+    #[serde(default = "default_channel_ttl")]
+    pub ttl: u64,
+}
+
+fn default_channel_ttl() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+/// Which OAuth flow to use when authenticating against Google, e.g.
+///
+/// ```json
+/// { "flow": "service_account", "key_file": "service_account.json" }
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "flow", rename_all = "snake_case")]
+pub enum AuthConfig {
+    /// Prints a code that someone has to enter on another device whenever the
+    /// persisted token is lost.
+    Device {
+        #[serde(default = "default_client_secret")]
+        client_secret: String,
+        #[serde(default = "default_token_file")]
+        token_file: String,
+    },
+    /// Prints a URL that someone has to open, and then either reads the code
+    /// from stdin or, with `http_redirect`, gets it by redirecting the browser
+    /// to a local server.
+    Installed {
+        #[serde(default = "default_client_secret")]
+        client_secret: String,
+        #[serde(default = "default_token_file")]
+        token_file: String,
+        #[serde(default)]
+        http_redirect: bool,
+    },
+    /// Authenticates as a service account, which needs no human at all. The
+    /// calendar has to be shared with the service account, or `subject` set to
+    /// a user to impersonate using domain-wide delegation.
+    ServiceAccount {
+        key_file: String,
+        #[serde(default)]
+        subject: Option<String>,
+    },
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self::Device {
+            client_secret: default_client_secret(),
+            token_file: default_token_file(),
+        }
+    }
+}
+
+fn default_client_secret() -> String {
+    "client_secret.json".to_string()
+}
+
+fn default_token_file() -> String {
+    "tokens.json".to_string()
+}
+
+/// A reminder sent some time before a meeting.
+///
+/// The message is a template where the following are replaced:
+///
+/// - `{summary}`: the name of the event.
+/// - `{date}`: the date of the meeting, e.g. `2021-03-14`.
+/// - `{time}`: the start time of the meeting, e.g. `17:15`. Empty for all-day
+///   meetings.
+/// - `{relative}`: the start time as a Discord timestamp, shown as e.g. `in 2
+///   days`.
+/// - `{location}`: ` Location: <location>.` if the event has a location.
+/// - `{description}`: the description of the event on a line of its own, if
+///   it has one. Long descriptions are cut short.
+/// - `{link}`: a link to the event in Google Calendar, if there is one.
+/// - `{mention}`: a mention of the meetup role in `DISCORD_MEETUP_ROLE_ID`.
+///   Nothing else in the message can ping anyone.
+/// - `{agenda}`: the current agenda.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Reminder {
+    /// How many minutes before the start of the meeting to send the reminder.
+    /// Should be unique among the reminders since a meeting only gets one
+    /// reminder per offset.
+    pub offset: i64,
+    pub message: String,
+    /// The channel to send the reminder to. Defaults to
+    /// `DISCORD_SECRET_CHANNEL`.
+    #[serde(default)]
+    pub channel: Option<u64>,
+}
+
+/// Decides which calendar events are meetings. An event matches if it matches
+/// every criterion that is set. An event that matches several matchers is
+/// only handled by the first one.
+///
+/// Matchers aren't only for board meetings. Public events can be announced to
+/// everyone who has the meetup role with e.g.
+///
+/// ```json
+/// {
+///     "name": "Public events",
+///     "extended_properties": { "public": "true" },
+///     "reminders": [{
+///         "offset": 1440,
+///         "message": "{mention} {summary} {relative}, {date} {time}.{location}{description}\n{link}",
+///         "channel": 123456789012345678
+///     }]
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MeetingMatcher {
+    /// Only used for logging.
+    pub name: String,
+    #[serde(default)]
+    pub summary: Option<SummaryMatcher>,
+    /// Matches if the description contains any of these, ignoring case.
+    #[serde(default)]
+    pub description_keywords: Vec<String>,
+    /// The `colorId` of the event.
+    #[serde(default)]
+    pub color_id: Option<String>,
+    /// Extended properties that the event has to have, either private or
+    /// shared.
+    #[serde(default)]
+    pub extended_properties: HashMap<String, String>,
+    /// Whether all-day events match. Their reminders are relative to the
+    /// start of the day in the event's time zone.
+    #[serde(default)]
+    pub all_day: bool,
+    /// The length in minutes of meetings created with `/meeting schedule`.
+    #[serde(default = "default_length")]
+    pub length: i64,
+    #[serde(default = "default_reminders")]
+    pub reminders: Vec<Reminder>,
+}
+
+impl MeetingMatcher {
+    /// The summary of meetings created with `/meeting schedule`.
+    pub fn event_summary(&self) -> String {
+        match &self.summary {
+            Some(SummaryMatcher::Exact(summary)) => summary.clone(),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// The start of `event` if it matches.
+    pub fn start_of(&self, event: &Event) -> Option<DateTime<Utc>> {
+        if let Some(summary) = &self.summary {
+            if !summary.matches(event.summary()) {
+                return None;
+            }
+        }
+        if !self.description_keywords.is_empty() {
+            let description = event.description().as_deref()?.to_lowercase();
+            if !self
+                .description_keywords
+                .iter()
+                .any(|keyword| description.contains(&keyword.to_lowercase()))
+            {
+                return None;
+            }
+        }
+        if self.color_id.is_some() && &self.color_id != event.color_id() {
+            return None;
+        }
+        let properties = event.extended_properties();
+        if !self.extended_properties.iter().all(|(key, value)| {
+            properties.private().get(key) == Some(value)
+                || properties.shared().get(key) == Some(value)
+        }) {
+            return None;
+        }
+        if event.start().date().is_some() && !self.all_day {
+            return None;
+        }
+        event
+            .start()
+            .instant()
+            .map(|start| start.with_timezone(&Utc))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryMatcher {
+    Exact(String),
+    Regex(Pattern),
+}
+
+impl SummaryMatcher {
+    pub fn matches(&self, summary: &str) -> bool {
+        match self {
+            Self::Exact(s) => s == summary,
+            Self::Regex(Pattern(re)) => re.is_match(summary),
+        }
+    }
+}
+
+/// A regex that can be read from the config.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(Regex);
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Ok(Self(Regex::new(&s)?))
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+fn default_meetings() -> Vec<MeetingMatcher> {
+    vec![MeetingMatcher {
+        name: "Styrelsemöte".to_string(),
+        summary: Some(SummaryMatcher::Exact("Styrelsemöte".to_string())),
+        description_keywords: Vec::new(),
+        color_id: None,
+        extended_properties: HashMap::new(),
+        all_day: false,
+        length: default_length(),
+        reminders: default_reminders(),
+    }]
+}
+
+fn default_length() -> i64 {
+    60
+}
+
+fn default_reminders() -> Vec<Reminder> {
+    vec![Reminder {
+        offset: 60,
+        message: "Meeting at {time}!{location}\n{agenda}".to_string(),
+        channel: None,
+    }]
+}
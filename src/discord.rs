@@ -1,20 +1,24 @@
 use std::{
     convert::{TryFrom, TryInto},
     ops::RangeBounds,
+    sync::OnceLock,
 };
 
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use color_eyre::eyre::{anyhow, bail};
 use futures_util::stream::StreamExt;
+use regex::{Captures, Regex};
 use tokio::{
     join,
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, oneshot},
 };
 use twilight_cache_inmemory::{InMemoryCache, ResourceType};
 use twilight_gateway::{
     cluster::{Cluster, ShardScheme},
     Event,
 };
-use twilight_http::{request::AuditLogReason, Client as HttpClient};
+use twilight_http::Client as HttpClient;
 use twilight_model::{
     application::{
         callback::{CallbackData, InteractionResponse},
@@ -23,27 +27,61 @@ use twilight_model::{
             ApplicationCommand, Interaction,
         },
     },
+    channel::message::{AllowedMentions, MessageFlags},
     gateway::payload::incoming::InteractionCreate,
     gateway::Intents,
+    guild::PartialMember,
     id::{
-        marker::{ChannelMarker, RoleMarker},
+        marker::{ApplicationMarker, ChannelMarker, GuildMarker, InteractionMarker, RoleMarker},
         Id,
     },
 };
 
 use crate::{
-    agenda::{Agenda, AgendaPoint},
-    calendar::{self, model::Timestamp},
+    agenda::{self, Agenda, AgendaPoint},
+    calendar::{
+        self,
+        model::{resolve_local, Timestamp},
+        CalendarClient,
+    },
+    config::{self, Config},
+    error::RequestError,
     kodapa, GenericRange,
 };
 
+use self::commands::CommandDefinition;
+
+mod commands;
+mod roles;
+
+/// Discord refuses longer messages.
+const MESSAGE_LIMIT: usize = 2000;
+/// How much of an event's description goes into messages, so that there's
+/// room for the rest of the template.
+const DESCRIPTION_LIMIT: usize = 1000;
+/// The response to `/meeting` when events are read from somewhere else, since
+/// only Google calendars can be changed.
+const NO_GOOGLE_CALENDAR: &str = "Meetings can only be changed in a Google calendar";
+
+/// What commands need to get at the calendar.
+#[derive(Clone)]
+struct Calendar {
+    /// Only set for Google calendars, the only ones that can be changed.
+    client: Option<&'static CalendarClient>,
+    requests: mpsc::UnboundedSender<calendar::Request>,
+}
+
 pub async fn handle(
     token: String,
     _agenda_sender: mpsc::UnboundedSender<AgendaPoint>,
     event_receiver: broadcast::Receiver<kodapa::Event>,
+    calendar_requests: mpsc::UnboundedSender<calendar::Request>,
+    config: Config,
+    calendar: Option<&'static CalendarClient>,
 ) {
     let http = Box::new(HttpClient::new(token.clone()));
     let http = Box::leak(http) as &HttpClient;
+    let config = Box::leak(Box::new(config)) as &Config;
     let secret_channel: Id<ChannelMarker> = Id::new(
         std::env::var("DISCORD_SECRET_CHANNEL")
             .expect("missing DISCORD_SECRET_CHANNEL")
@@ -57,34 +95,202 @@ pub async fn handle(
             .unwrap(),
     );
 
+    let admin_channel: Id<ChannelMarker> = std::env::var("DISCORD_ADMIN_CHANNEL")
+        .ok()
+        .map(|id| Id::new(id.parse().unwrap()))
+        .unwrap_or(secret_channel);
+
+    if let Err(e) = commands::register(http, &InteractionCommand::definitions(config)).await {
+        println!("unable to register commands: {}", e);
+    }
+    check_roles(http, config, meetup_role).await;
+
+    let calendar = Calendar {
+        client: calendar,
+        requests: calendar_requests,
+    };
     let _e1 = join!(
-        handle_discord_events(token, http, secret_channel, meetup_role),
-        handle_reminder_events(event_receiver, http, secret_channel),
+        handle_discord_events(token, http, config, calendar, secret_channel, meetup_role),
+        handle_reminder_events(
+            event_receiver,
+            http,
+            secret_channel,
+            admin_channel,
+            meetup_role
+        ),
     );
 }
 
+/// Warns about roles that the bot isn't allowed to hand out.
+async fn check_roles(http: &HttpClient, config: &Config, meetup_role: Id<RoleMarker>) {
+    let roles: Vec<_> = std::iter::once(meetup_role)
+        .chain(
+            config
+                .roles
+                .iter()
+                .filter_map(|role| Id::new_checked(role.role)),
+        )
+        .collect();
+    let problems = match guild_id() {
+        Ok(guild_id) => roles::check_hierarchy(http, guild_id, &roles).await,
+        Err(e) => Err(e),
+    };
+    match problems {
+        Ok(problems) => {
+            for problem in problems {
+                println!("unable to manage roles: {}", problem);
+            }
+        }
+        Err(e) => println!("unable to check roles: {}", e),
+    }
+}
+
+/// Registers the slash commands, for `kodapa register`.
+pub async fn register_commands(token: String, config: &Config) -> color_eyre::Result<()> {
+    commands::register(
+        &HttpClient::new(token),
+        &InteractionCommand::definitions(config),
+    )
+    .await
+}
+
+/// Reads `DISCORD_GUILD_ID`, the server that the bot is in.
+fn guild_id() -> color_eyre::Result<Id<GuildMarker>> {
+    std::env::var("DISCORD_GUILD_ID")
+        .map_err(|_| anyhow!("missing DISCORD_GUILD_ID"))?
+        .parse::<u64>()
+        .ok()
+        .and_then(Id::new_checked)
+        .ok_or_else(|| anyhow!("invalid DISCORD_GUILD_ID"))
+}
+
 async fn handle_reminder_events(
     mut receiver: broadcast::Receiver<kodapa::Event>,
     http: &HttpClient,
     secret_channel: Id<ChannelMarker>,
+    admin_channel: Id<ChannelMarker>,
+    meetup_role: Id<RoleMarker>,
 ) {
-    while let Ok(event) = receiver.recv().await {
-        match event {
-            kodapa::Event::Reminder { event } => {
-                http.create_message(secret_channel)
-                    .content(&get_meeting_string(&event))
-                    .unwrap()
-                    .exec()
-                    .await
-                    .unwrap();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!("missed {} calendar events", skipped);
+                continue;
             }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let mut delivery = None;
+        let (channel, content, roles) = match event {
+            kodapa::Event::Reminder {
+                event,
+                reminder,
+                delivery: reminder_delivery,
+            } => {
+                delivery = Some(reminder_delivery);
+                let channel = reminder
+                    .channel
+                    .and_then(Id::new_checked)
+                    .unwrap_or(secret_channel);
+                // Only the template can ping, not what's in the event.
+                let mention = format!("<@&{}>", meetup_role);
+                let roles = reminder
+                    .message
+                    .contains("{mention}")
+                    .then_some(meetup_role);
+                let content =
+                    get_meeting_string(&event, &reminder.message.replace("{mention}", &mention));
+                (channel, content, roles)
+            }
+            kodapa::Event::MeetingCancelled { event } => (
+                secret_channel,
+                format!("Cancelled: {}", describe_meeting(&event)),
+                None,
+            ),
+            kodapa::Event::MeetingRescheduled { event, old_start } => (
+                secret_channel,
+                format!(
+                    "Moved: {} (was {})",
+                    get_meeting_string(&event, "{summary} {date} {time}, {relative}."),
+                    old_start
+                        .with_timezone(&config::time_zone())
+                        .format("%Y-%m-%d %H:%M"),
+                ),
+                None,
+            ),
+            kodapa::Event::MeetingLocationChanged {
+                event,
+                old_location,
+            } => (
+                secret_channel,
+                format!(
+                    "New location: {} (was {})",
+                    describe_meeting(&event),
+                    old_location.as_deref().unwrap_or("nowhere"),
+                ),
+                None,
+            ),
+            kodapa::Event::AuthorizationRequired {
+                url,
+                code,
+                expires_at,
+            } => (
+                admin_channel,
+                format!(
+                    "I've lost access to the calendar. Enter `{}` at {} <t:{}:R> to give it back.",
+                    code,
+                    url,
+                    expires_at.timestamp(),
+                ),
+                None,
+            ),
+        };
+        let result = send_message(http, channel, &content, roles).await;
+        if let Err(e) = &result {
+            println!("unable to send message to {}: {}", channel, e);
+        }
+        if let Some(delivery) = delivery {
+            delivery.report(result.is_ok());
         }
     }
 }
 
+/// Sends `content`, cut short if it's too long for Discord.
+async fn send_message(
+    http: &HttpClient,
+    channel: Id<ChannelMarker>,
+    content: &str,
+    roles: Option<Id<RoleMarker>>,
+) -> color_eyre::Result<()> {
+    http.create_message(channel)
+        .content(&truncate(content, MESSAGE_LIMIT))?
+        .allowed_mentions(allowed_mentions(roles))
+        .exec()
+        .await?;
+    Ok(())
+}
+
+/// Cuts `text` short at `limit` characters, ending it with an ellipsis.
+fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(limit - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Only lets `roles` be pinged, so that e.g. an `@everyone` in an event
+/// description stays harmless.
+fn allowed_mentions(roles: impl IntoIterator<Item = Id<RoleMarker>>) -> AllowedMentions {
+    AllowedMentions::builder().role_ids(roles).build()
+}
+
 async fn handle_discord_events(
     token: String,
     http: &'static HttpClient,
+    config: &'static Config,
+    calendar: Calendar,
     secret_channel: Id<ChannelMarker>,
     meetup_role: Id<RoleMarker>,
 ) {
@@ -122,6 +328,8 @@ async fn handle_discord_events(
             shard_id,
             event,
             http,
+            config,
+            calendar.clone(),
             secret_channel,
             meetup_role,
         ));
@@ -132,13 +340,23 @@ async fn handle_event(
     shard_id: u64,
     event: Event,
     http: &HttpClient,
+    config: &Config,
+    calendar: Calendar,
     secret_channel: Id<ChannelMarker>,
     meetup_role: Id<RoleMarker>,
 ) {
     match event {
         Event::GatewayHeartbeatAck => (),
         Event::InteractionCreate(interaction) => {
-            handle_interaction(*interaction, &http, secret_channel, meetup_role).await;
+            handle_interaction(
+                *interaction,
+                http,
+                config,
+                &calendar,
+                secret_channel,
+                meetup_role,
+            )
+            .await;
         }
         Event::ShardConnected(_) => {
             println!("Connected on shard {}", shard_id);
@@ -165,18 +383,209 @@ fn find_option<'a>(
     })
 }
 
-/// The kinds of interactions we support. Should match the file
-/// `register_commands.py` which needs to be run if any changes to commands is
-/// to be made.
-//TODO: Use hyper instead of Python to register.
+fn find_bool_option<'a>(
+    search_name: &str,
+    iter: impl IntoIterator<Item = &'a CommandDataOption>,
+) -> Option<bool> {
+    iter.into_iter().find_map(|option| match option {
+        CommandDataOption {
+            name,
+            value: CommandOptionValue::Boolean(value),
+            ..
+        } if name == search_name => Some(*value),
+        _ => None,
+    })
+}
+
+/// The kinds of interactions we support. See [`InteractionCommand::definitions`]
+/// for how they are registered with Discord.
 enum InteractionCommand {
     Add { title: String },
     Agenda,
-    Meetup(bool), // enable or disable
+    Clear(ClearCommand),
+    Meeting(MeetingCommand),
+    Meetup(MeetupCommand),
+    Subscribe(Option<u64>), // a configured role id, or none to list them
+    NextMeeting,
     RemoveOne(usize),
     RemoveMany(Option<usize>, Option<usize>),
 }
 
+/// What `/clear` does. Without options it only shows what would be cleared.
+enum ClearCommand {
+    Preview,
+    Confirm,
+    Undo,
+}
+
+/// Subcommands of `/meetup`, which manages the meetup role of whoever uses it.
+enum MeetupCommand {
+    Enable,
+    Disable,
+    Status,
+}
+
+/// Subcommands of `/meeting`. They all act on meetings of the first kind in
+/// the config, i.e. the board meeting. `Move` and `Cancel` act on the next
+/// meeting unless a date is given.
+enum MeetingCommand {
+    Schedule {
+        start: DateTime<Tz>,
+        location: Option<String>,
+    },
+    Move {
+        from: Option<NaiveDate>,
+        start: DateTime<Tz>,
+    },
+    Cancel {
+        date: Option<NaiveDate>,
+    },
+}
+
+impl TryFrom<CommandDataOption> for MeetingCommand {
+    type Error = color_eyre::Report;
+
+    fn try_from(option: CommandDataOption) -> Result<Self, Self::Error> {
+        let options = match option.value {
+            CommandOptionValue::SubCommand(options) => options,
+            _ => bail!("expected a subcommand"),
+        };
+        let start = || -> Result<_, Self::Error> {
+            parse_date_time(
+                find_option("date", options.iter()).ok_or_else(|| anyhow!("no date"))?,
+                find_option("time", options.iter()).ok_or_else(|| anyhow!("no time"))?,
+            )
+        };
+        match option.name.as_str() {
+            "schedule" => Ok(Self::Schedule {
+                start: start()?,
+                location: find_option("location", options.iter()).map(str::to_string),
+            }),
+            "move" => Ok(Self::Move {
+                from: find_option("from", options.iter())
+                    .map(parse_date)
+                    .transpose()?,
+                start: start()?,
+            }),
+            "cancel" => Ok(Self::Cancel {
+                date: find_option("date", options.iter())
+                    .map(parse_date)
+                    .transpose()?,
+            }),
+            name => bail!("unknown subcommand {}", name),
+        }
+    }
+}
+
+fn parse_date(date: &str) -> color_eyre::Result<NaiveDate> {
+    Ok(NaiveDate::parse_from_str(date, "%Y-%m-%d")?)
+}
+
+fn parse_date_time(date: &str, time: &str) -> color_eyre::Result<DateTime<Tz>> {
+    let time = NaiveTime::parse_from_str(time, "%H:%M")?;
+    config::time_zone()
+        .from_local_datetime(&parse_date(date)?.and_time(time))
+        .single()
+        .ok_or_else(|| anyhow!("ambiguous or skipped time"))
+}
+
+impl InteractionCommand {
+    /// The commands as registered with Discord. Keep in sync with the parser
+    /// below.
+    fn definitions(config: &Config) -> Vec<CommandDefinition> {
+        let date = |description| commands::string("date", description, true);
+        let time = |description| commands::string("time", description, true);
+        let subscribe = (!config.roles.is_empty()).then(|| {
+            CommandDefinition::new("subscribe", "Get or stop getting notifications")
+                .option(commands::choice(
+                    "role",
+                    "The notifications to toggle, or leave out to list them",
+                    false,
+                    config
+                        .roles
+                        .iter()
+                        .map(|role| (role.name.clone(), role.role.to_string())),
+                ))
+                .default_permission(true)
+        });
+        vec![
+            CommandDefinition::new("add", "Add a thing to the agenda").option(commands::string(
+                "title",
+                "What to add",
+                true,
+            )),
+            CommandDefinition::new("agenda", "List the current agenda"),
+            CommandDefinition::new("clear", "Clear the current agenda")
+                .option(commands::boolean(
+                    "confirm",
+                    "Clear the agenda instead of showing what would be cleared",
+                    false,
+                ))
+                .option(commands::boolean(
+                    "undo",
+                    "Bring back the agenda that was just cleared",
+                    false,
+                )),
+            CommandDefinition::new("meeting", "Schedule, move or cancel a board meeting")
+                .option(commands::subcommand(
+                    "schedule",
+                    "Schedule a new meeting",
+                    vec![
+                        date("The date of the meeting (YYYY-MM-DD)"),
+                        time("The start time of the meeting (HH:MM)"),
+                        commands::string("location", "Where the meeting is", false),
+                    ],
+                ))
+                .option(commands::subcommand(
+                    "move",
+                    "Move the next meeting",
+                    vec![
+                        date("The new date of the meeting (YYYY-MM-DD)"),
+                        time("The new start time of the meeting (HH:MM)"),
+                        commands::string(
+                            "from",
+                            "Move the meeting on this date instead of the next one (YYYY-MM-DD)",
+                            false,
+                        ),
+                    ],
+                ))
+                .option(commands::subcommand(
+                    "cancel",
+                    "Cancel the next meeting",
+                    vec![commands::string(
+                        "date",
+                        "Cancel the meeting on this date instead of the next one (YYYY-MM-DD)",
+                        false,
+                    )],
+                )),
+            CommandDefinition::new("meetup", "Configure meetup notifications")
+                .option(commands::subcommand(
+                    "enable",
+                    "Enable meetup notifications",
+                    Vec::new(),
+                ))
+                .option(commands::subcommand(
+                    "disable",
+                    "Disable meetup notifications",
+                    Vec::new(),
+                ))
+                .option(commands::subcommand(
+                    "status",
+                    "Show whether you get meetup notifications",
+                    Vec::new(),
+                ))
+                .default_permission(true),
+            CommandDefinition::new("nextmeeting", "Show when the next board meeting is"),
+            CommandDefinition::new("remove", "Remove one or more items from the agenda")
+                .option(commands::string("which", "Which item(s) to remove", true))
+                .default_permission(true),
+        ]
+        .into_iter()
+        .chain(subscribe)
+        .collect()
+    }
+}
+
 impl TryFrom<CommandData> for InteractionCommand {
     type Error = color_eyre::Report;
 
@@ -189,17 +598,43 @@ impl TryFrom<CommandData> for InteractionCommand {
                 Ok(Self::Add { title })
             }
             "agenda" => Ok(Self::Agenda),
-            "meetup" => {
-                for option in data.options {
-                    let CommandDataOption { name, .. } = option;
-                    match name.as_str() {
-                        "enable" => return Ok(Self::Meetup(true)),
-                        "disable" => return Ok(Self::Meetup(false)),
-                        _ => (),
-                    }
+            "clear" => {
+                let confirm = find_bool_option("confirm", data.options.iter()).unwrap_or(false);
+                let undo = find_bool_option("undo", data.options.iter()).unwrap_or(false);
+                match (confirm, undo) {
+                    (false, false) => Ok(Self::Clear(ClearCommand::Preview)),
+                    (true, false) => Ok(Self::Clear(ClearCommand::Confirm)),
+                    (false, true) => Ok(Self::Clear(ClearCommand::Undo)),
+                    (true, true) => bail!("can't both clear and undo"),
                 }
-                todo!()
             }
+            "meeting" => {
+                let subcommand = data
+                    .options
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("no subcommand"))?;
+                Ok(Self::Meeting(subcommand.try_into()?))
+            }
+            "meetup" => {
+                let subcommand = data
+                    .options
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("no subcommand"))?;
+                Ok(Self::Meetup(match subcommand.name.as_str() {
+                    "enable" => MeetupCommand::Enable,
+                    "disable" => MeetupCommand::Disable,
+                    "status" => MeetupCommand::Status,
+                    name => bail!("unknown subcommand {}", name),
+                }))
+            }
+            "nextmeeting" => Ok(Self::NextMeeting),
+            "subscribe" => Ok(Self::Subscribe(
+                find_option("role", data.options.iter())
+                    .map(str::parse)
+                    .transpose()?,
+            )),
             "remove" => {
                 let which = find_option("which", data.options.iter())
                     .unwrap()
@@ -223,6 +658,8 @@ impl TryFrom<CommandData> for InteractionCommand {
 async fn handle_interaction(
     interaction: InteractionCreate,
     http: &HttpClient,
+    config: &Config,
+    calendar: &Calendar,
     secret_channel: Id<ChannelMarker>,
     meetup_role: Id<RoleMarker>,
 ) {
@@ -230,6 +667,7 @@ async fn handle_interaction(
         Interaction::Ping(_) => println!("pong (interaction)"),
         Interaction::ApplicationCommand(application_command) => {
             let ApplicationCommand {
+                application_id,
                 channel_id,
                 data,
                 id,
@@ -238,125 +676,420 @@ async fn handle_interaction(
                 guild_id,
                 ..
             } = *application_command;
-            let response = if channel_id != secret_channel {
-                "Commands are not valid in this channel".to_string()
-            } else {
-                match data.try_into() {
-                    Ok(InteractionCommand::Add { title }) => {
-                        Agenda::push_write(AgendaPoint {
-                            title: title.to_string(),
-                            adder: member
-                                .and_then(|m| m.nick.or(m.user.map(|user| user.name)))
-                                .unwrap_or_else(|| "?".to_string()),
-                            timestamp: chrono::Local::now(),
-                        });
-                        format!("Added {}", title)
+            // Failures are only shown to whoever used the command.
+            let mut ephemeral = false;
+            // Commands that talk to the calendar may take longer than Discord
+            // waits for an answer, so they answer right away and fill in the
+            // response when it's done.
+            let mut deferred = false;
+            let response = match data.try_into() {
+                Err(_) => {
+                    ephemeral = true;
+                    "Error parsing command".to_string()
+                }
+                // Anyone can manage their own roles, from anywhere, and the
+                // replies are only for them.
+                Ok(InteractionCommand::Meetup(command)) => {
+                    ephemeral = true;
+                    match handle_meetup_command(command, http, guild_id, member, meetup_role).await
+                    {
+                        Ok(response) => response,
+                        Err(e) => e.to_string(),
                     }
-                    Ok(InteractionCommand::Agenda) => get_agenda_string(),
-                    Ok(InteractionCommand::RemoveOne(n)) => {
-                        let removed = get_agenda_points(n..=n);
-                        match Agenda::remove_one(n) {
-                            Ok(_) => format!("Removed:\n{}", removed),
-                            Err(e) => e,
-                        }
+                }
+                Ok(InteractionCommand::Subscribe(role)) => {
+                    ephemeral = true;
+                    match handle_subscribe_command(role, http, config, guild_id, member).await {
+                        Ok(response) => response,
+                        Err(e) => e.to_string(),
                     }
-                    Ok(InteractionCommand::RemoveMany(lower, upper)) => {
-                        let range = GenericRange(lower, upper);
-                        let removed = get_agenda_points(range.clone());
-                        match Agenda::remove_many(range) {
-                            Ok(_) => format!("Removed:\n{}", removed),
-                            Err(e) => e,
+                }
+                Ok(_) if channel_id != secret_channel => {
+                    ephemeral = true;
+                    "Commands are not valid in this channel".to_string()
+                }
+                Ok(InteractionCommand::Add { title }) => {
+                    Agenda::push_write(AgendaPoint {
+                        title: title.to_string(),
+                        adder: member
+                            .and_then(|m| m.nick.or(m.user.map(|user| user.name)))
+                            .unwrap_or_else(|| "?".to_string()),
+                        timestamp: Utc::now().into(),
+                    });
+                    format!("Added {}", title)
+                }
+                Ok(InteractionCommand::Agenda) => get_agenda_string(),
+                Ok(InteractionCommand::Clear(command)) => handle_clear_command(command),
+                Ok(InteractionCommand::Meeting(command)) => match calendar.client {
+                    Some(client) => {
+                        deferred = defer_response(http, application_id, id, &token).await;
+                        match handle_meeting_command(command, config, client, &calendar.requests)
+                            .await
+                        {
+                            Ok(response) => response,
+                            Err(e) => format!("Error talking to the calendar: {}", e),
                         }
                     }
-                    Ok(InteractionCommand::Meetup(enable)) => {
-                        if let Some(member) = member {
-                            let has_meetup_role =
-                                member.roles.iter().any(|role| role == &meetup_role);
-                            if enable && has_meetup_role {
-                                "You already have this role".to_string()
-                            } else if !enable && !has_meetup_role {
-                                "You don't have this role".to_string()
-                            } else if enable {
-                                http.add_guild_member_role(
-                                    guild_id.unwrap(),
-                                    member.user.unwrap().id,
-                                    meetup_role,
-                                )
-                                .reason("Requested by user")
-                                .unwrap()
-                                .exec()
-                                .await
-                                .unwrap();
-                                "ok".to_string()
-                            } else {
-                                http.remove_guild_member_role(
-                                    guild_id.unwrap(),
-                                    member.user.unwrap().id,
-                                    meetup_role,
-                                )
-                                .reason("Requested by user")
-                                .unwrap()
-                                .exec()
-                                .await
-                                .unwrap();
-                                "ok".to_string()
-                            }
-                        } else {
-                            "Missing member".to_string()
-                        }
+                    None => NO_GOOGLE_CALENDAR.to_string(),
+                },
+                Ok(InteractionCommand::NextMeeting) => {
+                    // Only Google is asked, other sources answer from the last
+                    // sync.
+                    if calendar.client.is_some() {
+                        deferred = defer_response(http, application_id, id, &token).await;
+                    }
+                    match get_next_meeting_string(config, calendar).await {
+                        Ok(response) => response,
+                        Err(e) => format!("Error talking to the calendar: {}", e),
+                    }
+                }
+                Ok(InteractionCommand::RemoveOne(n)) => {
+                    let removed = get_agenda_points(n..=n);
+                    match Agenda::remove_one(n) {
+                        Ok(_) => format!("Removed:\n{}", removed),
+                        Err(e) => e,
+                    }
+                }
+                Ok(InteractionCommand::RemoveMany(lower, upper)) => {
+                    let range = GenericRange(lower, upper);
+                    let removed = get_agenda_points(range.clone());
+                    match Agenda::remove_many(range) {
+                        Ok(_) => format!("Removed:\n{}", removed),
+                        Err(e) => e,
                     }
-                    Err(_) => "Error parsing command".to_string(),
                 }
             };
             println!("response: {:?}", response);
-            let application_id = http
-                .current_user_application()
-                .exec()
+            let response = truncate(&response, MESSAGE_LIMIT);
+            let result = if deferred {
+                update_response(http, application_id, &token, &response).await
+            } else {
+                http.interaction(application_id)
+                    .interaction_callback(
+                        id,
+                        &token,
+                        &InteractionResponse::ChannelMessageWithSource(CallbackData {
+                            allowed_mentions: None,
+                            components: None,
+                            content: Some(response),
+                            embeds: Default::default(),
+                            flags: ephemeral.then_some(MessageFlags::EPHEMERAL),
+                            tts: None,
+                        }),
+                    )
+                    .exec()
+                    .await
+                    .map(|_| ())
+                    .map_err(Into::into)
+            };
+            if let Err(e) = result {
+                println!("unable to respond to interaction: {}", e);
+            }
+        }
+        i => println!("unhandled interaction: {:?}", i),
+    }
+}
+
+/// Tells Discord that the response will come later, see [`update_response`].
+/// Returns whether it worked, otherwise the response has to be sent as usual.
+async fn defer_response(
+    http: &HttpClient,
+    application_id: Id<ApplicationMarker>,
+    id: Id<InteractionMarker>,
+    token: &str,
+) -> bool {
+    let result = http
+        .interaction(application_id)
+        .interaction_callback(
+            id,
+            token,
+            &InteractionResponse::DeferredChannelMessageWithSource(CallbackData {
+                allowed_mentions: None,
+                components: None,
+                content: None,
+                embeds: Default::default(),
+                flags: None,
+                tts: None,
+            }),
+        )
+        .exec()
+        .await;
+    if let Err(e) = &result {
+        println!("unable to defer interaction response: {}", e);
+    }
+    result.is_ok()
+}
+
+/// Fills in the response to an interaction that was deferred.
+async fn update_response(
+    http: &HttpClient,
+    application_id: Id<ApplicationMarker>,
+    token: &str,
+    content: &str,
+) -> color_eyre::Result<()> {
+    http.interaction(application_id)
+        .update_interaction_original(token)
+        .content(Some(content))?
+        .allowed_mentions(allowed_mentions(None))
+        .exec()
+        .await?;
+    Ok(())
+}
+
+async fn handle_meetup_command(
+    command: MeetupCommand,
+    http: &HttpClient,
+    guild_id: Option<Id<GuildMarker>>,
+    member: Option<PartialMember>,
+    meetup_role: Id<RoleMarker>,
+) -> color_eyre::Result<String> {
+    let guild_id = guild_id.ok_or_else(|| anyhow!("Meetups can only be managed in a server"))?;
+    let member = member.ok_or_else(|| anyhow!("Missing member"))?;
+    let user = member.user.ok_or_else(|| anyhow!("Missing user"))?;
+    let has_meetup_role = member.roles.contains(&meetup_role);
+    let response = match command {
+        MeetupCommand::Status if has_meetup_role => {
+            "You get meetup notifications. Use `/meetup disable` to stop."
+        }
+        MeetupCommand::Status => {
+            "You don't get meetup notifications. Use `/meetup enable` to start."
+        }
+        MeetupCommand::Enable if has_meetup_role => "You already get meetup notifications",
+        MeetupCommand::Disable if !has_meetup_role => "You don't get meetup notifications",
+        MeetupCommand::Enable => {
+            roles::set_role(http, guild_id, &user, meetup_role, true, "meetup")
                 .await
-                .unwrap()
-                .model()
+                .map_err(|e| anyhow!("Unable to give you the meetup role: {}", e))?;
+            "You now get meetup notifications"
+        }
+        MeetupCommand::Disable => {
+            roles::set_role(http, guild_id, &user, meetup_role, false, "meetup")
                 .await
-                .unwrap()
-                .id;
-            http.interaction(application_id)
-                .interaction_callback(
-                    id,
-                    &token,
-                    &InteractionResponse::ChannelMessageWithSource(CallbackData {
-                        allowed_mentions: None,
-                        components: None,
-                        content: Some(response),
-                        embeds: Default::default(),
-                        flags: None,
-                        tts: None,
-                    }),
+                .map_err(|e| anyhow!("Unable to remove your meetup role: {}", e))?;
+            "You no longer get meetup notifications"
+        }
+    };
+    Ok(response.to_string())
+}
+
+async fn handle_subscribe_command(
+    role: Option<u64>,
+    http: &HttpClient,
+    config: &Config,
+    guild_id: Option<Id<GuildMarker>>,
+    member: Option<PartialMember>,
+) -> color_eyre::Result<String> {
+    let guild_id = guild_id.ok_or_else(|| anyhow!("Roles can only be managed in a server"))?;
+    let member = member.ok_or_else(|| anyhow!("Missing member"))?;
+    let user = member
+        .user
+        .as_ref()
+        .ok_or_else(|| anyhow!("Missing user"))?;
+    let has_role =
+        |id| Id::new_checked(id).is_some_and(|id: Id<RoleMarker>| member.roles.contains(&id));
+
+    let role = match role {
+        Some(role) => role,
+        None => {
+            let roles = config
+                .roles
+                .iter()
+                .map(|role| {
+                    let mark = if has_role(role.role) { "x" } else { " " };
+                    format!("[{}] {}", mark, role.name)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Ok(format!(
+                "```\n{}\n```\nUse `/subscribe role: ...` to toggle one.",
+                roles
+            ));
+        }
+    };
+    let subscription = config
+        .roles
+        .iter()
+        .find(|subscription| subscription.role == role)
+        .ok_or_else(|| anyhow!("That role can't be subscribed to"))?;
+    let id = Id::new_checked(role).ok_or_else(|| anyhow!("Invalid role"))?;
+    let enable = !has_role(role);
+    roles::set_role(http, guild_id, user, id, enable, "subscribe")
+        .await
+        .map_err(|e| anyhow!("Unable to change your roles: {}", e))?;
+    Ok(if enable {
+        format!("You now get {} notifications", subscription.name)
+    } else {
+        format!("You no longer get {} notifications", subscription.name)
+    })
+}
+
+fn handle_clear_command(command: ClearCommand) -> String {
+    let undo_minutes = agenda::UNDO_WINDOW.as_secs() / 60;
+    match command {
+        ClearCommand::Preview => {
+            let agenda = Agenda::read();
+            if agenda.points.is_empty() {
+                "The agenda is already empty".to_string()
+            } else {
+                format!(
+                    "This would clear:\n{}\nUse `/clear confirm: True` to clear it.",
+                    agenda
                 )
-                .exec()
-                .await
-                .unwrap();
+            }
         }
-        i => println!("unhandled interaction: {:?}", i),
+        ClearCommand::Confirm => {
+            let cleared = Agenda::clear(agenda::CLEARED_FILE);
+            if cleared.is_empty() {
+                "The agenda is already empty".to_string()
+            } else {
+                format!(
+                    "Cleared:\n{}\nUse `/clear undo: True` within {} minutes to bring it back.",
+                    Agenda { points: cleared },
+                    undo_minutes,
+                )
+            }
+        }
+        ClearCommand::Undo => match Agenda::undo_clear(agenda::CLEARED_FILE) {
+            Ok(restored) => format!("Restored:\n{}", Agenda { points: restored }),
+            Err(e) => e,
+        },
     }
 }
 
-fn get_meeting_string(event: &calendar::model::events::Event) -> String {
-    format!(
-        "Meeting at {}!{}\n{}",
-        event
-            .start()
-            .try_into()
-            .ok()
-            .as_ref()
-            .and_then(|dt: &Timestamp| dt.date_time())
-            .map(|dt| dt.format("%H:%M").to_string())
-            .unwrap_or_else(String::new),
-        if let Some(location) = event.location() {
-            format!(" Location: {}.", location)
-        } else {
-            String::new()
+/// Fills in a reminder message template. See [`crate::config::Reminder`] for
+/// what is replaced.
+///
+/// Everything is replaced in one pass, so that an event can't smuggle e.g. the
+/// secret agenda into a public announcement by having `{agenda}` in it.
+fn get_meeting_string(event: &calendar::model::events::Event, template: &str) -> String {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER.get_or_init(|| Regex::new(r"\{(\w+)\}").unwrap());
+
+    let start: Option<Timestamp> = event.start().try_into().ok();
+    placeholder
+        .replace_all(template, |captures: &Captures| match &captures[1] {
+            "summary" => event.summary().to_string(),
+            "date" => match &start {
+                Some(Timestamp::DateTime(date_time)) => date_time.format("%Y-%m-%d").to_string(),
+                Some(Timestamp::Date(date)) => date.format("%Y-%m-%d").to_string(),
+                None => String::new(),
+            },
+            // All-day events have no time, but do start at some point.
+            "time" => start
+                .as_ref()
+                .and_then(|start| start.date_time())
+                .map(|dt| dt.format("%H:%M").to_string())
+                .unwrap_or_default(),
+            "relative" => event
+                .start()
+                .instant()
+                .map(|dt| format!("<t:{}:R>", dt.timestamp()))
+                .unwrap_or_default(),
+            "location" => match event.location() {
+                Some(location) => format!(" Location: {}.", location),
+                None => String::new(),
+            },
+            "description" => event
+                .description()
+                .as_ref()
+                .map(|description| format!("\n{}", truncate(description, DESCRIPTION_LIMIT)))
+                .unwrap_or_default(),
+            "link" => event.html_link().clone().unwrap_or_default(),
+            "agenda" => get_agenda_string(),
+            // Unknown placeholders, and `{mention}` which is filled in by the
+            // caller, are left alone.
+            _ => captures[0].to_string(),
+        })
+        .into_owned()
+}
+
+async fn handle_meeting_command(
+    command: MeetingCommand,
+    config: &Config,
+    client: &CalendarClient,
+    requests: &mpsc::UnboundedSender<calendar::Request>,
+) -> Result<String, RequestError> {
+    let matcher = match config.meetings.first() {
+        Some(matcher) => matcher,
+        None => return Ok("No meetings are configured".to_string()),
+    };
+    let find = |date: Option<NaiveDate>| {
+        let (from, until) = match date.and_then(day_bounds) {
+            Some((from, until)) => (from, Some(until)),
+            None => (config::now(), None),
+        };
+        calendar::meetings::find(client, matcher, from, until)
+    };
+    match command {
+        MeetingCommand::Schedule { start, location } => {
+            let meeting =
+                calendar::meetings::schedule(client, requests, matcher, start, location).await?;
+            Ok(format!("Scheduled {}", describe_meeting(&meeting)))
+        }
+        MeetingCommand::Move { from, start } => match find(from).await? {
+            Some((meeting, _)) => {
+                let old = describe_meeting(&meeting);
+                let meeting =
+                    calendar::meetings::reschedule(client, requests, matcher, &meeting, start)
+                        .await?;
+                Ok(format!("Moved {} to {}", old, describe_meeting(&meeting)))
+            }
+            None => Ok("Found no meeting to move".to_string()),
         },
-        get_agenda_string(),
-    )
+        MeetingCommand::Cancel { date } => match find(date).await? {
+            Some((meeting, _)) => {
+                calendar::meetings::cancel(client, requests, &meeting).await?;
+                Ok(format!("Cancelled {}", describe_meeting(&meeting)))
+            }
+            None => Ok("Found no meeting to cancel".to_string()),
+        },
+    }
+}
+
+/// Asks Google if we can, since the cache may be a minute old. Other sources
+/// are read in full, so the last sync has every meeting.
+async fn get_next_meeting_string(
+    config: &Config,
+    calendar: &Calendar,
+) -> Result<String, RequestError> {
+    let matcher = match config.meetings.first() {
+        Some(matcher) => matcher,
+        None => return Ok("No meetings are configured".to_string()),
+    };
+    let meeting = match calendar.client {
+        Some(client) => calendar::meetings::find(client, matcher, config::now(), None)
+            .await?
+            .map(|(meeting, _)| meeting),
+        None => {
+            let (reply, meeting) = oneshot::channel();
+            let request = calendar::Request::NextMeeting { matcher: 0, reply };
+            // The calendar task only goes away when the bot does.
+            match calendar.requests.send(request) {
+                Ok(()) => meeting.await.ok().flatten(),
+                Err(_) => None,
+            }
+        }
+    };
+    Ok(match meeting {
+        Some(meeting) => get_meeting_string(
+            &meeting,
+            "Next meeting: {summary} {date} {time} ({relative}).{location}\n{agenda}",
+        ),
+        None => "No upcoming meetings".to_string(),
+    })
+}
+
+/// The start of `date` and of the day after.
+fn day_bounds(date: NaiveDate) -> Option<(DateTime<Tz>, DateTime<Tz>)> {
+    let time_zone = config::time_zone();
+    let from = resolve_local(time_zone, date.and_hms(0, 0, 0))?;
+    let until = resolve_local(time_zone, date.succ().and_hms(0, 0, 0))?;
+    Some((from, until))
+}
+
+/// E.g. `Styrelsemöte 2021-03-14 17:15. Location: Café Java.`
+fn describe_meeting(event: &calendar::model::events::Event) -> String {
+    get_meeting_string(event, "{summary} {date} {time}.{location}")
 }
 
 fn get_agenda_string() -> String {
@@ -364,15 +1097,12 @@ fn get_agenda_string() -> String {
     if points.is_empty() {
         "Empty agenda".to_string()
     } else {
-        format!(
-            "{}",
-            points
-                .iter()
-                .enumerate()
-                .map(|(i, point)| format!("{}. {}", i + 1, point))
-                .collect::<Vec<_>>()
-                .join("\n"),
-        )
+        points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| format!("{}. {}", i + 1, point))
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
@@ -388,3 +1118,105 @@ fn get_agenda_points(range: impl RangeBounds<usize>) -> String {
             .join("\n")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::utc;
+
+    #[test]
+    fn parse_date_time_around_dst() {
+        let parse = |date, time| parse_date_time(date, time).map(|t| t.with_timezone(&Utc));
+        assert_eq!(
+            parse("2024-03-31", "01:59").unwrap(),
+            utc("2024-03-31T00:59:00Z")
+        );
+        assert_eq!(
+            parse("2024-03-31", "03:00").unwrap(),
+            utc("2024-03-31T01:00:00Z")
+        );
+        assert_eq!(
+            parse("2024-10-27", "03:00").unwrap(),
+            utc("2024-10-27T02:00:00Z")
+        );
+        // Skipped and ambiguous times have to be entered some other way.
+        assert!(parse("2024-03-31", "02:30").is_err());
+        assert!(parse("2024-10-27", "02:30").is_err());
+    }
+
+    #[test]
+    fn meeting_strings() {
+        use crate::calendar::model::{events::Event, GCalTimestamp};
+
+        let template = "{summary} {date} {time} {relative}";
+        let start = Tz::Europe__Stockholm.ymd(2030, 3, 14).and_hms(17, 15, 0);
+        let meeting = Event::new(
+            "meeting".to_string(),
+            "Möte".to_string(),
+            GCalTimestamp::from_date_time(start),
+            GCalTimestamp::from_date_time(start + chrono::Duration::hours(1)),
+        );
+        assert_eq!(
+            get_meeting_string(&meeting, template),
+            "Möte 2030-03-14 17:15 <t:1899735300:R>"
+        );
+        let day = NaiveDate::from_ymd(2030, 3, 14);
+        let all_day = Event::new(
+            "all-day".to_string(),
+            "Årsmöte".to_string(),
+            GCalTimestamp::from_date(day),
+            GCalTimestamp::from_date(day.succ()),
+        );
+        assert_eq!(
+            get_meeting_string(&all_day, template),
+            "Årsmöte 2030-03-14  <t:1899673200:R>"
+        );
+    }
+
+    #[test]
+    fn meeting_strings_are_filled_in_once() {
+        use crate::calendar::model::{events::Event, GCalTimestamp};
+
+        let start = Tz::Europe__Stockholm.ymd(2030, 3, 14).and_hms(17, 15, 0);
+        let meeting = Event::new(
+            "meetup".to_string(),
+            "Meetup {agenda}".to_string(),
+            GCalTimestamp::from_date_time(start),
+            GCalTimestamp::from_date_time(start + chrono::Duration::hours(1)),
+        )
+        .with_description(Some("Bring {agenda} and {mention}".to_string()));
+        assert_eq!(
+            get_meeting_string(&meeting, "{summary}:{description} {unknown}"),
+            "Meetup {agenda}:\nBring {agenda} and {mention} {unknown}"
+        );
+    }
+
+    #[test]
+    fn truncating() {
+        assert_eq!(truncate("Styrelsemöte", 12), "Styrelsemöte");
+        assert_eq!(truncate("Styrelsemöte", 10), "Styrelsem…");
+        let long = "ö".repeat(3000);
+        assert_eq!(
+            truncate(&long, MESSAGE_LIMIT).chars().count(),
+            MESSAGE_LIMIT
+        );
+    }
+
+    #[test]
+    fn day_bounds_around_dst() {
+        let bounds = |date| {
+            let (from, until) = day_bounds(parse_date(date).unwrap()).unwrap();
+            (from.with_timezone(&Utc), until.with_timezone(&Utc))
+        };
+        // 23 hours.
+        assert_eq!(
+            bounds("2024-03-31"),
+            (utc("2024-03-30T23:00:00Z"), utc("2024-03-31T22:00:00Z"))
+        );
+        // 25 hours.
+        assert_eq!(
+            bounds("2024-10-27"),
+            (utc("2024-10-26T22:00:00Z"), utc("2024-10-27T23:00:00Z"))
+        );
+    }
+}
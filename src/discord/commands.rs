@@ -0,0 +1,304 @@
+//! Registering our slash commands with Discord.
+//!
+//! The commands are declared by [`super::InteractionCommand::definitions`],
+//! next to the parser. They are compared with what is registered in the guild
+//! in `DISCORD_GUILD_ID`, and only commands that differ are created, updated
+//! or deleted, so this is cheap enough to do on every start.
+
+use color_eyre::eyre::anyhow;
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    application::command::{
+        BaseCommandOptionData, ChoiceCommandOptionData, Command, CommandOption,
+        CommandOptionChoice, OptionsCommandOptionData,
+    },
+    id::{marker::CommandMarker, Id},
+};
+
+/// A slash command as we want it to be registered.
+#[derive(Debug)]
+pub(super) struct CommandDefinition {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub options: Vec<CommandOption>,
+    /// Whether everyone can use the command. If not, it has to be allowed for
+    /// roles in the server settings.
+    pub default_permission: bool,
+}
+
+impl CommandDefinition {
+    pub fn new(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            description,
+            options: Vec::new(),
+            default_permission: false,
+        }
+    }
+
+    pub fn option(mut self, option: CommandOption) -> Self {
+        self.options.push(option);
+        self
+    }
+
+    pub fn default_permission(mut self, default_permission: bool) -> Self {
+        self.default_permission = default_permission;
+        self
+    }
+
+    /// Whether `command` is registered the way we want it.
+    fn matches(&self, command: &Command) -> bool {
+        command.description == self.description
+            && command.options == self.options
+            && command.default_permission.unwrap_or(true) == self.default_permission
+    }
+}
+
+pub(super) fn string(name: &str, description: &str, required: bool) -> CommandOption {
+    CommandOption::String(ChoiceCommandOptionData {
+        name: name.to_string(),
+        description: description.to_string(),
+        required,
+        ..ChoiceCommandOptionData::default()
+    })
+}
+
+/// A string option where one of `choices`, given as names and values, has to
+/// be picked.
+pub(super) fn choice(
+    name: &str,
+    description: &str,
+    required: bool,
+    choices: impl IntoIterator<Item = (String, String)>,
+) -> CommandOption {
+    CommandOption::String(ChoiceCommandOptionData {
+        name: name.to_string(),
+        description: description.to_string(),
+        required,
+        choices: choices
+            .into_iter()
+            .map(|(name, value)| CommandOptionChoice::String { name, value })
+            .collect(),
+        ..ChoiceCommandOptionData::default()
+    })
+}
+
+pub(super) fn boolean(name: &str, description: &str, required: bool) -> CommandOption {
+    CommandOption::Boolean(BaseCommandOptionData {
+        name: name.to_string(),
+        description: description.to_string(),
+        required,
+    })
+}
+
+pub(super) fn subcommand(
+    name: &str,
+    description: &str,
+    options: Vec<CommandOption>,
+) -> CommandOption {
+    CommandOption::SubCommand(OptionsCommandOptionData {
+        name: name.to_string(),
+        description: description.to_string(),
+        options,
+    })
+}
+
+/// Makes the commands registered in the guild match `definitions`.
+pub(super) async fn register(
+    http: &HttpClient,
+    definitions: &[CommandDefinition],
+) -> color_eyre::Result<()> {
+    let guild_id = super::guild_id()?;
+    let application_id = http
+        .current_user_application()
+        .exec()
+        .await?
+        .model()
+        .await?
+        .id;
+    let interaction = http.interaction(application_id);
+    let registered = interaction
+        .get_guild_commands(guild_id)
+        .exec()
+        .await?
+        .models()
+        .await?;
+
+    for change in plan(definitions, &registered)? {
+        match change {
+            Change::Create(definition) => {
+                println!("creating command {}", definition.name);
+                interaction
+                    .create_guild_command(guild_id)
+                    .chat_input(definition.name, definition.description)?
+                    .command_options(&definition.options)?
+                    .default_permission(definition.default_permission)
+                    .exec()
+                    .await?;
+            }
+            Change::Update(definition, command_id) => {
+                println!("updating command {}", definition.name);
+                interaction
+                    .update_guild_command(guild_id, command_id)
+                    .description(definition.description)
+                    .command_options(&definition.options)
+                    .exec()
+                    .await?;
+            }
+            Change::Delete(command_id, name) => {
+                println!("deleting command {}", name);
+                interaction
+                    .delete_guild_command(guild_id, command_id)
+                    .exec()
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Something that has to be done to make the registered commands match.
+#[derive(Debug)]
+enum Change<'a> {
+    Create(&'a CommandDefinition),
+    Update(&'a CommandDefinition, Id<CommandMarker>),
+    Delete(Id<CommandMarker>, &'a str),
+}
+
+/// What to create, update and delete to go from `registered` to
+/// `definitions`.
+fn plan<'a>(
+    definitions: &'a [CommandDefinition],
+    registered: &'a [Command],
+) -> color_eyre::Result<Vec<Change<'a>>> {
+    let mut changes = Vec::new();
+    for definition in definitions {
+        let existing = registered
+            .iter()
+            .find(|command| command.name == definition.name);
+        match existing {
+            Some(command) if definition.matches(command) => (),
+            // Editing a command can't change its default permission, but
+            // creating one with the same name replaces it.
+            Some(command)
+                if command.default_permission.unwrap_or(true) == definition.default_permission =>
+            {
+                let command_id = command
+                    .id
+                    .ok_or_else(|| anyhow!("command {} has no id", command.name))?;
+                changes.push(Change::Update(definition, command_id));
+            }
+            _ => changes.push(Change::Create(definition)),
+        }
+    }
+
+    for command in registered {
+        if definitions
+            .iter()
+            .all(|definition| definition.name != command.name)
+        {
+            if let Some(command_id) = command.id {
+                changes.push(Change::Delete(command_id, &command.name));
+            }
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use twilight_model::application::command::CommandType;
+
+    /// How `definition` is registered, with the id `id`.
+    fn registered(definition: &CommandDefinition, id: u64) -> Command {
+        Command {
+            application_id: None,
+            default_permission: Some(definition.default_permission),
+            description: definition.description.to_string(),
+            guild_id: None,
+            id: Some(Id::new(id)),
+            kind: CommandType::ChatInput,
+            name: definition.name.to_string(),
+            options: definition.options.clone(),
+            version: Id::new(1),
+        }
+    }
+
+    fn definitions() -> Vec<CommandDefinition> {
+        vec![
+            CommandDefinition::new("add", "Add a point to the agenda").option(string(
+                "title",
+                "The point",
+                true,
+            )),
+            CommandDefinition::new("meetup", "Meetup notifications")
+                .option(subcommand("enable", "Get notified", vec![]))
+                .default_permission(true),
+        ]
+    }
+
+    /// The changes, e.g. `update add 1`.
+    fn describe(changes: Vec<Change>) -> Vec<String> {
+        changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Create(definition) => format!("create {}", definition.name),
+                Change::Update(definition, id) => format!("update {} {}", definition.name, id),
+                Change::Delete(id, name) => format!("delete {} {}", name, id),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged() {
+        let definitions = definitions();
+        let registered: Vec<_> = definitions
+            .iter()
+            .enumerate()
+            .map(|(i, definition)| registered(definition, i as u64 + 1))
+            .collect();
+        assert!(plan(&definitions, &registered).unwrap().is_empty());
+
+        // Discord leaves it out when it's the default.
+        let mut registered = registered;
+        registered[1].default_permission = None;
+        assert!(plan(&definitions, &registered).unwrap().is_empty());
+    }
+
+    #[test]
+    fn changed_options() {
+        let definitions = definitions();
+        let mut add = registered(&definitions[0], 1);
+        add.options.push(boolean("urgent", "Put it first", false));
+        let mut meetup = registered(&definitions[1], 2);
+        meetup.description = "Something else".to_string();
+        assert_eq!(
+            describe(plan(&definitions, &[add, meetup]).unwrap()),
+            ["update add 1", "update meetup 2"]
+        );
+    }
+
+    #[test]
+    fn changed_default_permission() {
+        let definitions = definitions();
+        let mut add = registered(&definitions[0], 1);
+        add.default_permission = None;
+        let meetup = registered(&definitions[1], 2);
+        assert_eq!(
+            describe(plan(&definitions, &[add, meetup]).unwrap()),
+            ["create add"]
+        );
+    }
+
+    #[test]
+    fn new_and_removed() {
+        let definitions = definitions();
+        let meetup = registered(&definitions[1], 2);
+        let old = registered(&CommandDefinition::new("old", "Not used anymore"), 3);
+        assert_eq!(
+            describe(plan(&definitions, &[meetup, old]).unwrap()),
+            ["create add", "delete old 3"]
+        );
+    }
+}
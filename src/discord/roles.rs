@@ -0,0 +1,211 @@
+//! Roles that members give themselves, like the meetup role and the ones in
+//! [`crate::config::SubscriptionRole`].
+
+use twilight_http::{request::AuditLogReason, Client as HttpClient};
+use twilight_model::{
+    guild::{Permissions, Role},
+    id::{
+        marker::{GuildMarker, RoleMarker},
+        Id,
+    },
+    user::User,
+};
+
+/// Gives `user` the role or takes it away. The audit log says who asked for it
+/// and with which command.
+pub(super) async fn set_role(
+    http: &HttpClient,
+    guild_id: Id<GuildMarker>,
+    user: &User,
+    role: Id<RoleMarker>,
+    enable: bool,
+    command: &str,
+) -> color_eyre::Result<()> {
+    let reason = format!(
+        "Requested by {}#{:04} with /{}",
+        user.name, user.discriminator, command
+    );
+    if enable {
+        http.add_guild_member_role(guild_id, user.id, role)
+            .reason(&reason)?
+            .exec()
+            .await?;
+    } else {
+        http.remove_guild_member_role(guild_id, user.id, role)
+            .reason(&reason)?
+            .exec()
+            .await?;
+    }
+    Ok(())
+}
+
+/// Makes sure that the bot is allowed to hand out `roles`, i.e. that it has
+/// the Manage Roles permission and that its highest role is above all of
+/// them. Returns a description of every problem found.
+pub(super) async fn check_hierarchy(
+    http: &HttpClient,
+    guild_id: Id<GuildMarker>,
+    roles: &[Id<RoleMarker>],
+) -> color_eyre::Result<Vec<String>> {
+    let user_id = http.current_user().exec().await?.model().await?.id;
+    let member = http
+        .guild_member(guild_id, user_id)
+        .exec()
+        .await?
+        .model()
+        .await?;
+    let guild_roles = http.roles(guild_id).exec().await?.models().await?;
+
+    Ok(hierarchy_problems(
+        guild_id,
+        &member.roles,
+        &guild_roles,
+        roles,
+    ))
+}
+
+/// The problems [`check_hierarchy`] reports, for a bot with `member_roles` in
+/// a guild with `guild_roles`.
+fn hierarchy_problems(
+    guild_id: Id<GuildMarker>,
+    member_roles: &[Id<RoleMarker>],
+    guild_roles: &[Role],
+    roles: &[Id<RoleMarker>],
+) -> Vec<String> {
+    // Everyone has the @everyone role, which has the same id as the guild.
+    let own_roles: Vec<_> = guild_roles
+        .iter()
+        .filter(|role| member_roles.contains(&role.id) || role.id.cast() == guild_id)
+        .collect();
+    let permissions = own_roles
+        .iter()
+        .fold(Permissions::empty(), |permissions, role| {
+            permissions | role.permissions
+        });
+    let highest = own_roles
+        .iter()
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0);
+
+    let mut problems = Vec::new();
+    if !permissions.intersects(Permissions::MANAGE_ROLES | Permissions::ADMINISTRATOR) {
+        problems.push("the bot doesn't have the Manage Roles permission".to_string());
+    }
+    for id in roles {
+        match guild_roles.iter().find(|role| role.id == *id) {
+            None => problems.push(format!("there is no role with id {}", id)),
+            Some(role) if role.managed => {
+                problems.push(format!("{} is managed by an integration", role.name))
+            }
+            Some(role) if role.position >= highest => problems.push(format!(
+                "{} has to be moved below the bot's highest role",
+                role.name
+            )),
+            Some(_) => (),
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: u64 = 1;
+    const BOT: u64 = 2;
+    const BELOW: u64 = 3;
+    const ABOVE: u64 = 4;
+
+    fn role(id: u64, name: &str, position: i64, permissions: Permissions) -> Role {
+        Role {
+            color: 0,
+            hoist: false,
+            icon: None,
+            id: Id::new(id),
+            managed: false,
+            mentionable: false,
+            name: name.to_string(),
+            permissions,
+            position,
+            tags: None,
+            unicode_emoji: None,
+        }
+    }
+
+    fn guild_roles(bot_permissions: Permissions) -> Vec<Role> {
+        vec![
+            role(GUILD, "@everyone", 0, Permissions::empty()),
+            role(BELOW, "below", 1, Permissions::empty()),
+            role(BOT, "bot", 2, bot_permissions),
+            role(ABOVE, "above", 3, Permissions::empty()),
+        ]
+    }
+
+    fn problems(bot_permissions: Permissions, roles: &[u64]) -> Vec<String> {
+        let roles: Vec<_> = roles.iter().copied().map(Id::new).collect();
+        hierarchy_problems(
+            Id::new(GUILD),
+            &[Id::new(BOT)],
+            &guild_roles(bot_permissions),
+            &roles,
+        )
+    }
+
+    #[test]
+    fn roles_below_the_bot() {
+        assert!(problems(Permissions::MANAGE_ROLES, &[BELOW]).is_empty());
+        assert!(problems(Permissions::ADMINISTRATOR, &[BELOW]).is_empty());
+    }
+
+    #[test]
+    fn roles_above_the_bot() {
+        assert_eq!(
+            problems(Permissions::MANAGE_ROLES, &[BELOW, ABOVE, BOT]),
+            [
+                "above has to be moved below the bot's highest role",
+                "bot has to be moved below the bot's highest role",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_manage_roles() {
+        assert_eq!(
+            problems(Permissions::SEND_MESSAGES, &[BELOW]),
+            ["the bot doesn't have the Manage Roles permission"]
+        );
+    }
+
+    #[test]
+    fn permissions_from_everyone() {
+        let mut guild_roles = guild_roles(Permissions::empty());
+        guild_roles[0].permissions = Permissions::MANAGE_ROLES;
+        let problems = hierarchy_problems(
+            Id::new(GUILD),
+            &[Id::new(BOT)],
+            &guild_roles,
+            &[Id::new(BELOW)],
+        );
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn missing_and_managed_roles() {
+        let mut guild_roles = guild_roles(Permissions::MANAGE_ROLES);
+        guild_roles[1].managed = true;
+        let problems = hierarchy_problems(
+            Id::new(GUILD),
+            &[Id::new(BOT)],
+            &guild_roles,
+            &[Id::new(BELOW), Id::new(5)],
+        );
+        assert_eq!(
+            problems,
+            [
+                "below is managed by an integration",
+                "there is no role with id 5",
+            ]
+        );
+    }
+}
@@ -1,11 +1,85 @@
+use hyper::{Method, StatusCode};
+use std::fmt;
+
+use crate::calendar::{ics, model::ApiError};
+
 #[derive(Debug)]
 pub enum RequestError {
+    /// The sync token used for an incremental sync is no longer valid and a
+    /// full sync is required (HTTP 410 Gone).
+    Gone,
+    /// We couldn't get an access token, e.g. because nobody has authorized us.
+    TokenError(yup_oauth2::Error),
+    /// The API answered with an error status. `error` is the error Google
+    /// sent, if the body could be parsed.
+    ApiError {
+        status: StatusCode,
+        error: Option<ApiError>,
+    },
+    /// The server didn't answer in time.
+    Timeout,
+    /// A server kept redirecting us.
+    TooManyRedirects,
+    /// A local calendar file couldn't be read.
+    IoError(std::io::Error),
     HttpError(hyper::http::Error),
     HyperError(hyper::Error),
     ResponseError(BodyParseError),
     UrlParseError(url::ParseError),
 }
 
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gone => write!(f, "sync token is no longer valid"),
+            Self::TokenError(e) => write!(f, "unable to get token: {}", e),
+            Self::ApiError {
+                status,
+                error: Some(error),
+            } => write!(f, "api error: {}: {}", status, error.message()),
+            Self::ApiError {
+                status,
+                error: None,
+            } => write!(f, "api error: {}", status),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::TooManyRedirects => write!(f, "too many redirects"),
+            Self::IoError(e) => write!(f, "io error: {}", e),
+            Self::HttpError(e) => write!(f, "http error: {}", e),
+            Self::HyperError(e) => write!(f, "hyper error: {}", e),
+            Self::ResponseError(e) => write!(f, "response error: {}", e),
+            Self::UrlParseError(e) => write!(f, "url parse error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl RequestError {
+    /// Whether a `method` request might succeed if it is sent again later,
+    /// i.e. if we hit a rate limit or the server had a problem.
+    ///
+    /// Server errors are only retried for methods that are idempotent, since
+    /// e.g. an insert may have gone through anyway and sending it again would
+    /// create the event twice. Rate limited requests were never carried out.
+    pub fn is_retryable(&self, method: &Method) -> bool {
+        match self {
+            Self::ApiError { status, error } => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+                    // Calendar also uses 403 for rate limits.
+                    || (*status == StatusCode::FORBIDDEN
+                        && error.as_ref().is_some_and(|error| {
+                            error.has_reason("rateLimitExceeded")
+                                || error.has_reason("userRateLimitExceeded")
+                        }))
+                    || (status.is_server_error()
+                        && [Method::GET, Method::PUT, Method::PATCH, Method::DELETE]
+                            .contains(method))
+            }
+            _ => false,
+        }
+    }
+}
+
 impl From<hyper::http::Error> for RequestError {
     fn from(e: hyper::http::Error) -> Self {
         Self::HttpError(e)
@@ -25,13 +99,71 @@ impl From<url::ParseError> for RequestError {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum BodyParseError {
     BodyError(hyper::Error),
     JsonError(serde_json::Error),
+    IcsError(ics::ParseError),
+    XmlError(quick_xml::Error),
 }
 
+impl fmt::Display for BodyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BodyError(e) => write!(f, "unable to read body: {}", e),
+            Self::JsonError(e) => write!(f, "unable to parse json: {}", e),
+            Self::IcsError(e) => write!(f, "unable to parse ics: {}", e),
+            Self::XmlError(e) => write!(f, "unable to parse xml: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BodyParseError {}
+
 impl From<serde_json::Error> for BodyParseError {
     fn from(e: serde_json::Error) -> Self {
         Self::JsonError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: u16, reason: Option<&str>) -> RequestError {
+        let errors = reason
+            .map(|reason| {
+                serde_json::json!([{ "domain": "usageLimits", "reason": reason, "message": "" }])
+            })
+            .unwrap_or_else(|| serde_json::json!([]));
+        let error = serde_json::json!({ "code": status, "message": "", "errors": errors });
+        RequestError::ApiError {
+            status: StatusCode::from_u16(status).unwrap(),
+            error: Some(serde_json::from_value(error).unwrap()),
+        }
+    }
+
+    #[test]
+    fn retryable_errors() {
+        for method in &[Method::GET, Method::POST, Method::PATCH, Method::DELETE] {
+            assert!(api_error(429, None).is_retryable(method));
+            assert!(api_error(403, Some("rateLimitExceeded")).is_retryable(method));
+            assert!(api_error(403, Some("userRateLimitExceeded")).is_retryable(method));
+            assert!(!api_error(403, Some("forbidden")).is_retryable(method));
+            assert!(!api_error(403, None).is_retryable(method));
+            assert!(!api_error(404, None).is_retryable(method));
+            assert!(!RequestError::Timeout.is_retryable(method));
+        }
+    }
+
+    #[test]
+    fn server_errors_are_only_retried_when_idempotent() {
+        for status in &[500, 502, 503] {
+            for method in &[Method::GET, Method::PUT, Method::PATCH, Method::DELETE] {
+                assert!(api_error(*status, None).is_retryable(method));
+            }
+            // An insert may have gone through.
+            assert!(!api_error(*status, None).is_retryable(&Method::POST));
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use tokio::{
     join,
     sync::{broadcast, mpsc},
@@ -6,12 +7,38 @@ use tokio::{
 use crate::{
     agenda::{Agenda, AgendaPoint},
     calendar,
+    calendar::CalendarSource,
+    config::{Config, MeetingMatcher, Reminder},
 };
 
 #[derive(Debug, Clone)]
 pub enum Event {
+    /// `delivery` has to be reported once the reminder is posted, or it is
+    /// sent again.
     Reminder {
         event: calendar::model::events::Event,
+        reminder: Reminder,
+        delivery: calendar::Delivery,
+    },
+    /// A meeting was removed from the calendar. `event` is what the meeting
+    /// looked like before.
+    MeetingCancelled {
+        event: calendar::model::events::Event,
+    },
+    MeetingRescheduled {
+        event: calendar::model::events::Event,
+        old_start: DateTime<Utc>,
+    },
+    MeetingLocationChanged {
+        event: calendar::model::events::Event,
+        old_location: Option<String>,
+    },
+    /// Google needs someone to enter `code` at `url` before the bot can access
+    /// the calendar again.
+    AuthorizationRequired {
+        url: String,
+        code: String,
+        expires_at: DateTime<Utc>,
     },
 }
 
@@ -19,10 +46,18 @@ pub enum Event {
 pub async fn handle(
     agenda_receiver: mpsc::UnboundedReceiver<AgendaPoint>,
     event_sender: broadcast::Sender<Event>,
+    calendar_requests: mpsc::UnboundedReceiver<calendar::Request>,
+    config: Config,
+    source: Box<dyn CalendarSource>,
 ) {
     let (_e1, _e2) = join!(
         handle_agenda(agenda_receiver),
-        handle_reminders(event_sender.clone()),
+        handle_reminders(
+            event_sender.clone(),
+            calendar_requests,
+            config.meetings,
+            source
+        ),
     );
     println!("kodapa::handle: done");
 }
@@ -36,12 +71,19 @@ async fn handle_agenda(mut receiver: mpsc::UnboundedReceiver<AgendaPoint>) {
     }
 }
 
-/// Receives notifications when a reminder should be sent and sends it.
-async fn handle_reminders(event_sender: broadcast::Sender<Event>) {
+/// Receives notifications when a reminder should be sent, or when a meeting
+/// has changed, and sends it.
+async fn handle_reminders(
+    event_sender: broadcast::Sender<Event>,
+    calendar_requests: mpsc::UnboundedReceiver<calendar::Request>,
+    meetings: Vec<MeetingMatcher>,
+    source: Box<dyn CalendarSource>,
+) {
     let (calendar_tx, mut calendar_rx) = mpsc::unbounded_channel();
-    let (_e1, _e2) = join!(calendar::handle(calendar_tx), async {
+    let calendar = calendar::handle(calendar_tx, calendar_requests, meetings, source);
+    let (_e1, _e2) = join!(calendar, async {
         while let Some(event) = calendar_rx.recv().await {
-            event_sender.send(Event::Reminder { event }).unwrap();
+            event_sender.send(event).unwrap();
         }
     });
 }
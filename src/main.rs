@@ -7,13 +7,23 @@ use tokio::{
     sync::{broadcast, mpsc},
 };
 
-use self::agenda::AgendaPoint;
+use self::{
+    agenda::AgendaPoint,
+    calendar::{
+        push::Webhook, CalDavSource, CalendarClient, CalendarSource, DiscordFlowDelegate,
+        EventCache, IcsSource,
+    },
+    config::{Config, SourceConfig},
+};
 
 mod agenda;
 mod calendar;
+mod config;
 mod discord;
 mod error;
 mod kodapa;
+#[cfg(test)]
+mod test_util;
 
 #[allow(dead_code)]
 type Result<T> = ::std::result::Result<T, Box<dyn Error + Send + Sync>>;
@@ -40,15 +50,75 @@ impl RangeBounds<usize> for GenericRange {
 fn main() {
     color_eyre::install().unwrap();
     let discord_token = std::env::var("DISCORD_BOT_TOKEN").expect("missing DISCORD_BOT_TOKEN");
+    let config = Config::read();
+
+    // Commands are also registered on every start, but this doesn't need a
+    // calendar.
+    if std::env::args().nth(1).as_deref() == Some("register") {
+        let rt = tokio::runtime::Runtime::new().expect("unable to create async runtime");
+        if let Err(e) = rt.block_on(discord::register_commands(discord_token, &config)) {
+            println!("unable to register commands: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     let (agenda_sender, agenda_receiver) = mpsc::unbounded_channel::<AgendaPoint>();
     let (event_sender, event_receiver) = broadcast::channel::<kodapa::Event>(10);
+    let (calendar_sender, calendar_receiver) = mpsc::unbounded_channel::<calendar::Request>();
 
     let rt = tokio::runtime::Runtime::new().expect("unable to create async runtime");
     let _ = rt.block_on(async {
+        let (calendar, source): (_, Box<dyn CalendarSource>) = match &config.source {
+            SourceConfig::Google => {
+                let delegate = DiscordFlowDelegate::new(event_sender.clone());
+                let calendar = CalendarClient::new(&config, Some(delegate))
+                    .await
+                    .expect("unable to create calendar client");
+                let calendar = Box::leak(Box::new(calendar)) as &CalendarClient;
+                let mut cache = EventCache::new(calendar);
+                if let Some(webhook) = &config.calendar.webhook {
+                    let webhook = Webhook::bind(webhook.clone())
+                        .await
+                        .expect("unable to listen for calendar notifications");
+                    let (sender, receiver) = mpsc::unbounded_channel();
+                    cache = cache.with_notifications(receiver);
+                    tokio::spawn(async move {
+                        webhook.run(calendar, sender).await;
+                        // The channel is stopped, so there's no reason to stay.
+                        std::process::exit(0);
+                    });
+                }
+                (Some(calendar), Box::new(cache))
+            }
+            SourceConfig::Ics { location } => {
+                let source = IcsSource::new(location.clone(), &config.calendar)
+                    .expect("unable to create http client");
+                (None, Box::new(source))
+            }
+            SourceConfig::CalDav { url } => {
+                let source =
+                    CalDavSource::new(url, &config.calendar).expect("invalid CalDAV calendar");
+                (None, Box::new(source))
+            }
+        };
+
         join!(
-            discord::handle(discord_token, agenda_sender, event_receiver),
-            kodapa::handle(agenda_receiver, event_sender),
+            discord::handle(
+                discord_token,
+                agenda_sender,
+                event_receiver,
+                calendar_sender,
+                config.clone(),
+                calendar,
+            ),
+            kodapa::handle(
+                agenda_receiver,
+                event_sender,
+                calendar_receiver,
+                config,
+                source
+            ),
         )
     });
 }
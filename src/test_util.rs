@@ -0,0 +1,8 @@
+//! Helpers shared by the unit tests.
+
+use chrono::{DateTime, Utc};
+
+/// Parses an RFC 3339 timestamp, e.g. `2024-03-31T10:00:00Z`.
+pub fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}